@@ -0,0 +1,207 @@
+use crate::{
+    api_request::ApiRequest,
+    common_serde::{LoadError, Request},
+    common_tracing,
+    common_tracing::Logger,
+    error_code::CommonErrorCode,
+    middleware::{CorsLayer, ErrorLayer, LogLayer},
+    ApiResponse, CommonError, CorsPolicy,
+};
+use lambda_runtime::{
+    service_fn,
+    tower::{Service, ServiceBuilder, ServiceExt},
+    tracing::error,
+    Context, Error, LambdaEvent,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::Future;
+
+// Runs a Lambda function that serves API requests, e.g.
+// run_api_handler::<ApiGatewayProxyRequest, SignUpRequest, ApiGatewayProxyResponse, _, _, _>(
+//     CORS_POLICY,
+//     handler,
+// )
+// .await
+// The request is loaded from the event before the handler is called, so a request which fails to
+// load or validate is answered with its LoadError response, e.g. 4000003 ValidationFailed. The
+// handler may leave request_id empty in the response as it is filled from the context. Every
+// response, including the error ones, gets the CORS headers of the policy, which can be
+// CorsPolicy::DEFAULT for services not called from browsers
+pub async fn run_api_handler<Req, T, Resp, F, Fut, E>(
+    cors_policy: CorsPolicy<'static>,
+    handler: F,
+) -> Result<(), Error>
+where
+    Req: DeserializeOwned + ApiRequest + Logger + 'static,
+    T: Request,
+    Resp: for<'a> From<ApiResponse<'a>> + Serialize,
+    F: Fn(T, Req, Context) -> Fut + Clone + 'static,
+    Fut: Future<Output = Result<ApiResponse<'static>, E>> + 'static,
+    E: Into<anyhow::Error>,
+{
+    let service = service_fn(move |event: LambdaEvent<Req>| {
+        let handler = handler.clone();
+        async move { call_handler(&handler, event).await }
+    });
+
+    run_api_service::<Req, Resp, _>(
+        ServiceBuilder::new()
+            .layer(LogLayer)
+            .layer(CorsLayer(cors_policy))
+            .layer(ErrorLayer)
+            .service(service),
+    )
+    .await
+}
+
+async fn call_handler<Req, T, F, Fut, E>(
+    handler: &F,
+    event: LambdaEvent<Req>,
+) -> Result<ApiResponse<'static>, anyhow::Error>
+where
+    Req: ApiRequest,
+    T: Request,
+    F: Fn(T, Req, Context) -> Fut,
+    Fut: Future<Output = Result<ApiResponse<'static>, E>>,
+    E: Into<anyhow::Error>,
+{
+    let req = T::load(&event.payload)?;
+
+    handler(req, event.payload, event.context)
+        .await
+        .map_err(Into::into)
+}
+
+// Same as run_api_handler, but runs a tower service usually built from the layers in the
+// middleware module, e.g.
+// ServiceBuilder::new().layer(LogLayer).layer(CorsLayer(CORS_POLICY)).layer(ErrorLayer).service_fn(handler)
+pub async fn run_api_service<Req, Resp, S>(service: S) -> Result<(), Error>
+where
+    Req: DeserializeOwned,
+    Resp: for<'a> From<ApiResponse<'a>> + Serialize,
+    S: Service<LambdaEvent<Req>, Response = ApiResponse<'static>, Error = anyhow::Error> + Clone,
+{
+    common_tracing::init();
+
+    lambda_runtime::run(service_fn(|event: LambdaEvent<Req>| {
+        let mut service = service.clone();
+
+        async move {
+            let request_id = event.context.request_id.to_string();
+
+            let result = match service.ready().await {
+                Ok(service) => service.call(event).await,
+                Err(err) => Err(err),
+            };
+
+            let api_resp = into_api_resp(result, &request_id);
+            Ok::<_, Error>(Resp::from(api_resp))
+        }
+    }))
+    .await
+}
+
+pub(crate) fn into_api_resp<'a>(
+    result: Result<ApiResponse<'_>, anyhow::Error>,
+    request_id: &'a str,
+) -> ApiResponse<'a> {
+    let err = match result {
+        Ok(api_resp) => {
+            return ApiResponse {
+                request_id,
+                ..api_resp
+            }
+        }
+        Err(err) => err,
+    };
+
+    let err = match err.downcast::<CommonError>() {
+        Ok(err) => return err.into_api_resp(request_id),
+        Err(err) => err,
+    };
+
+    let err = match err.downcast::<LoadError>() {
+        Ok(err) => return err.into_api_resp(request_id),
+        Err(err) => err,
+    };
+
+    // Unknown errors may carry internal details, so they are only logged and never returned
+    error!(error = format!("{err:?}"));
+    CommonError::from(CommonErrorCode::InternalServerError).into_api_resp(request_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_lambda_events::apigw::ApiGatewayProxyRequest;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use tokio::runtime::Builder;
+    use validator::{Validate, ValidationError, ValidationErrors};
+
+    #[derive(Debug, Deserialize, crate::Request)]
+    struct GetUserRequest {
+        user_id: String,
+    }
+
+    impl Validate for GetUserRequest {
+        fn validate(&self) -> Result<(), ValidationErrors> {
+            let mut errs = ValidationErrors::new();
+
+            if self.user_id.is_empty() {
+                errs.add("user_id", ValidationError::new("length"));
+            }
+
+            if errs.is_empty() {
+                Ok(())
+            } else {
+                Err(errs)
+            }
+        }
+    }
+
+    async fn handler(
+        req: GetUserRequest,
+        _event: ApiGatewayProxyRequest,
+        _context: Context,
+    ) -> Result<ApiResponse<'static>, CommonError> {
+        Ok(ApiResponse {
+            message: req.user_id,
+            ..Default::default()
+        })
+    }
+
+    fn call(user_id: &str) -> ApiResponse<'static> {
+        let event = ApiGatewayProxyRequest {
+            path_parameters: HashMap::from([("user_id".to_string(), user_id.to_string())]),
+            ..Default::default()
+        };
+
+        let result = Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(call_handler(
+                &handler,
+                LambdaEvent::new(event, Context::default()),
+            ));
+
+        into_api_resp(result, "request_id")
+    }
+
+    #[test]
+    fn handler_gets_the_loaded_request() {
+        let api_resp = call("1");
+
+        assert_eq!(api_resp.code, CommonErrorCode::Ok.into());
+        assert_eq!(api_resp.message, "1");
+        assert_eq!(api_resp.request_id, "request_id");
+    }
+
+    #[test]
+    fn invalid_request_is_answered_without_calling_the_handler() {
+        let api_resp = call("");
+
+        assert_eq!(api_resp.code, CommonErrorCode::ValidationFailed.into());
+        assert_eq!(api_resp.payload["errors"]["user_id"][0]["code"], "length");
+    }
+}
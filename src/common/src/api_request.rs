@@ -0,0 +1,152 @@
+use crate::cookie::CookieJar;
+use aws_lambda_events::{
+    apigw::ApiGatewayProxyRequest,
+    http::{HeaderMap, Method},
+    query_map::QueryMap,
+};
+use std::{borrow::Cow, collections::HashMap};
+
+#[cfg(feature = "http_api")]
+use aws_lambda_events::apigw::ApiGatewayV2httpRequest;
+
+#[cfg(feature = "alb")]
+use aws_lambda_events::alb::AlbTargetGroupRequest;
+
+#[cfg(feature = "alb")]
+use percent_encoding::percent_decode_str;
+
+pub trait ApiRequest {
+    fn http_method(&self) -> &Method;
+    fn headers(&self) -> &HeaderMap;
+    fn body(&self) -> Option<&str>;
+    fn is_base64_encoded(&self) -> bool;
+    fn path_parameters(&self) -> Cow<'_, HashMap<String, String>>;
+    fn query_string_parameters(&self) -> Cow<'_, QueryMap>;
+
+    fn cookies(&self) -> CookieJar {
+        CookieJar::from(self.headers())
+    }
+}
+
+impl ApiRequest for ApiGatewayProxyRequest {
+    fn http_method(&self) -> &Method {
+        &self.http_method
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    fn is_base64_encoded(&self) -> bool {
+        self.is_base64_encoded
+    }
+
+    fn path_parameters(&self) -> Cow<'_, HashMap<String, String>> {
+        Cow::Borrowed(&self.path_parameters)
+    }
+
+    fn query_string_parameters(&self) -> Cow<'_, QueryMap> {
+        // Only multi_value_query_string_parameters keeps every value of a repeated key
+        if self.multi_value_query_string_parameters.is_empty() {
+            Cow::Borrowed(&self.query_string_parameters)
+        } else {
+            Cow::Borrowed(&self.multi_value_query_string_parameters)
+        }
+    }
+}
+
+#[cfg(feature = "http_api")]
+impl ApiRequest for ApiGatewayV2httpRequest {
+    fn http_method(&self) -> &Method {
+        &self.http_method
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    fn is_base64_encoded(&self) -> bool {
+        self.is_base64_encoded
+    }
+
+    fn path_parameters(&self) -> Cow<'_, HashMap<String, String>> {
+        Cow::Borrowed(&self.path_parameters)
+    }
+
+    fn query_string_parameters(&self) -> Cow<'_, QueryMap> {
+        // HTTP API joins the values of a repeated key with commas, so parse the raw query string
+        // instead to keep every value apart
+        let Some(raw_query_string) = self.raw_query_string.as_deref().filter(|q| !q.is_empty())
+        else {
+            return Cow::Borrowed(&self.query_string_parameters);
+        };
+
+        let mut query_string_parameters = HashMap::<String, Vec<String>>::new();
+
+        for (k, v) in form_urlencoded::parse(raw_query_string.as_bytes()) {
+            query_string_parameters
+                .entry(k.into_owned())
+                .or_default()
+                .push(v.into_owned());
+        }
+
+        Cow::Owned(query_string_parameters.into())
+    }
+
+    // HTTP API moves the Cookie header into its own field
+    fn cookies(&self) -> CookieJar {
+        self.cookies.iter().flatten().map(String::as_str).collect()
+    }
+}
+
+#[cfg(feature = "alb")]
+impl ApiRequest for AlbTargetGroupRequest {
+    fn http_method(&self) -> &Method {
+        &self.http_method
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    fn is_base64_encoded(&self) -> bool {
+        self.is_base64_encoded
+    }
+
+    fn path_parameters(&self) -> Cow<'_, HashMap<String, String>> {
+        // ALB has no notion of path parameters
+        Cow::Owned(HashMap::new())
+    }
+
+    fn query_string_parameters(&self) -> Cow<'_, QueryMap> {
+        // ALB passes query string parameters through without decoding them
+        let mut query_string_parameters = HashMap::<String, Vec<String>>::new();
+
+        let raw_query_string_parameters = if self.multi_value_query_string_parameters.is_empty() {
+            &self.query_string_parameters
+        } else {
+            &self.multi_value_query_string_parameters
+        };
+
+        for (k, v) in raw_query_string_parameters.iter() {
+            query_string_parameters
+                .entry(percent_decode_str(k).decode_utf8_lossy().into_owned())
+                .or_default()
+                .push(percent_decode_str(v).decode_utf8_lossy().into_owned());
+        }
+
+        Cow::Owned(query_string_parameters.into())
+    }
+}
@@ -1,14 +1,50 @@
-use crate::constants;
+use crate::{
+    api_request::ApiRequest,
+    common_enums::ContentEncoding,
+    cookie::{Cookie, CookieError},
+    cors::CorsPolicy,
+    error_code::{CommonErrorCode, ErrorCode},
+};
 use aws_lambda_events::{
     apigw::ApiGatewayProxyResponse,
-    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue},
+    encodings::Body,
+    http::{
+        header::{
+            Entry, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, LOCATION, SET_COOKIE, VARY,
+        },
+        HeaderMap, HeaderName, HeaderValue,
+    },
 };
+use lambda_runtime::tracing::error;
 use serde::Serialize;
 use serde_json::{json, Value};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io, mem,
+};
+
+#[cfg(feature = "compression")]
+use brotli::CompressorWriter;
+
+#[cfg(feature = "compression")]
+use flate2::{write::GzEncoder, Compression};
+
+#[cfg(feature = "compression")]
+use std::io::Write;
+
+#[cfg(feature = "http_api")]
+use aws_lambda_events::apigw::ApiGatewayV2httpResponse;
+
+#[cfg(feature = "alb")]
+use aws_lambda_events::{alb::AlbTargetGroupResponse, http::StatusCode};
+
+// Bodies smaller than this are not worth the CPU time to compress
+const MIN_COMPRESSED_BODY_LEN: usize = 1024;
 
 #[derive(Debug, PartialEq, Serialize)]
 pub struct ApiResponse<'a> {
-    pub code: u32,
+    pub code: ErrorCode,
 
     #[serde(skip)]
     pub headers: HeaderMap<HeaderValue>,
@@ -16,43 +52,512 @@ pub struct ApiResponse<'a> {
     pub message: String,
     pub payload: Value,
     pub request_id: &'a str,
+
+    #[serde(skip)]
+    pub body: ResponseBody,
+
+    #[serde(skip)]
+    pub content_encoding: Option<ContentEncoding>,
 }
 
 impl Default for ApiResponse<'_> {
     fn default() -> Self {
         Self {
-            code: 2000,
+            code: CommonErrorCode::Ok.into(),
             headers: HeaderMap::new(),
             message: "".to_string(),
             payload: json!({}),
             request_id: "",
+            body: ResponseBody::Envelope,
+            content_encoding: None,
         }
     }
 }
 
-impl From<ApiResponse<'_>> for ApiGatewayProxyResponse {
-    fn from(mut api_resp: ApiResponse<'_>) -> Self {
-        let status_code = api_resp.code.to_string()[..3].parse().unwrap();
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResponseBody {
+    // The JSON envelope made of code, message, payload and request_id
+    #[default]
+    Envelope,
+
+    Text {
+        content_type: String,
+        text: String,
+    },
+
+    Binary {
+        content_type: String,
+        bytes: Vec<u8>,
+    },
+
+    Empty,
+}
+
+// The pieces shared by every response event type, which guarantees they all carry the same envelope
+struct ApiResponseParts {
+    status_code: i64,
+    headers: HeaderMap,
+    multi_value_headers: HeaderMap,
+    body: Option<Body>,
+    is_base64_encoded: bool,
+}
 
-        if api_resp.message.is_empty() {
-            api_resp.message = constants::API_ERR_MSG_MAP.with(|api_err_msg_map| {
-                api_err_msg_map.get(&status_code).unwrap_or(&"").to_string()
-            });
+impl ApiResponseParts {
+    // An envelope that can always be built so that the runtime never crashes
+    fn fallback(request_id: &str, err: ApiResponseError) -> Self {
+        error!(error = err.to_string());
+        let code = ErrorCode::from(CommonErrorCode::InternalServerError);
+
+        let body = json!({
+            "code": code,
+            "message": code.message(),
+            "payload": {},
+            "request_id": request_id,
+        });
+
+        Self {
+            status_code: code.status_code().into(),
+            headers: HeaderMap::from_iter([(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )]),
+            multi_value_headers: HeaderMap::new(),
+            body: Some(Body::Text(body.to_string())),
+            is_base64_encoded: false,
         }
+    }
+}
+
+impl<'a> ApiResponse<'a> {
+    pub fn redirect(location: &str, request_id: &'a str) -> Result<Self, ApiResponseError> {
+        let location =
+            HeaderValue::from_str(location).map_err(|_| ApiResponseError::Header(LOCATION))?;
+
+        Ok(Self {
+            code: CommonErrorCode::Found.into(),
+            headers: HeaderMap::from_iter([(LOCATION, location)]),
+            request_id,
+            body: ResponseBody::Empty,
+            ..Default::default()
+        })
+    }
 
-        let body = serde_json::to_string(&api_resp).unwrap().into();
+    // Every cookie is kept as its own Set-Cookie header, so they end up in multi_value_headers
+    pub fn with_cookie(mut self, cookie: &Cookie) -> Result<Self, ApiResponseError> {
+        cookie.validate().map_err(ApiResponseError::Cookie)?;
+
+        let cookie = HeaderValue::from_str(&cookie.to_string())
+            .map_err(|_| ApiResponseError::Header(SET_COOKIE))?;
+
+        self.headers.append(SET_COOKIE, cookie);
+        Ok(self)
+    }
+
+    // Responses to disallowed origins get no CORS headers besides Vary, so the browser blocks them.
+    // run_api_handler and CorsLayer already do this for every response
+    pub fn with_cors(mut self, cors_policy: &CorsPolicy<'_>, event: &impl ApiRequest) -> Self {
+        cors_policy.apply(event, &mut self.headers);
+        self
+    }
+
+    pub fn with_compression(mut self, event: &impl ApiRequest) -> Self {
+        self.content_encoding = event
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|accept_encoding| accept_encoding.to_str().ok())
+            .and_then(ContentEncoding::negotiate);
+
+        self
+    }
+}
 
-        let headers = HeaderMap::from_iter(
-            [(CONTENT_TYPE, HeaderValue::from_static("application/json"))]
-                .into_iter()
-                .chain(api_resp.headers.into_iter().map(|(k, v)| (k.unwrap(), v))),
+impl ApiResponse<'_> {
+    fn try_into_parts(mut self) -> Result<ApiResponseParts, ApiResponseError> {
+        if self.message.is_empty() {
+            self.message = self.code.message().to_string();
+        }
+
+        let (content_type, body) = match mem::take(&mut self.body) {
+            ResponseBody::Envelope => {
+                let body = serde_json::to_vec(&self).map_err(ApiResponseError::Body)?;
+                (Some("application/json".to_string()), Some(body))
+            }
+            ResponseBody::Text { content_type, text } => (Some(content_type), Some(text.into())),
+            ResponseBody::Binary {
+                content_type,
+                bytes,
+            } => (Some(content_type), Some(bytes)),
+            ResponseBody::Empty => (None, None),
+        };
+
+        // Headers from the handler take precedence over the default ones
+        let mut headers = HeaderMap::new();
+
+        if let Some(content_type) = content_type {
+            let content_type = HeaderValue::from_str(&content_type)
+                .map_err(|_| ApiResponseError::Header(CONTENT_TYPE))?;
+
+            headers.insert(CONTENT_TYPE, content_type);
+        }
+
+        // A body the handler already encoded itself must not be compressed twice
+        let is_compressed = matches!(
+            (&body, self.content_encoding),
+            (Some(body), Some(_))
+                if body.len() >= MIN_COMPRESSED_BODY_LEN
+                    && !self.headers.contains_key(CONTENT_ENCODING)
         );
 
-        ApiGatewayProxyResponse {
-            status_code,
+        let body = match (body, self.content_encoding) {
+            (Some(body), Some(content_encoding)) if is_compressed => {
+                headers.insert(
+                    CONTENT_ENCODING,
+                    HeaderValue::from_static(content_encoding.as_str()),
+                );
+
+                Some(compress(&body, content_encoding).map_err(ApiResponseError::Compression)?)
+            }
+            (body, _) => body,
+        };
+
+        headers.extend(self.headers);
+
+        // Appended after the handler headers so that it adds to any Vary from them, e.g. Origin
+        if is_compressed {
+            headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        }
+
+        // Text stays readable in the response event while anything else has to be base64 encoded
+        let body = body.map(|body| match String::from_utf8(body) {
+            Ok(body) if !headers.contains_key(CONTENT_ENCODING) => Body::Text(body),
+            Ok(body) => Body::Binary(body.into_bytes()),
+            Err(err) => Body::Binary(err.into_bytes()),
+        });
+
+        let is_base64_encoded = matches!(body, Some(Body::Binary(_)));
+
+        // Headers having more than 1 value can only be returned through multi_value_headers
+        let multi_value_keys = headers
+            .keys()
+            .filter(|&k| headers.get_all(k).iter().nth(1).is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut multi_value_headers = HeaderMap::new();
+
+        for k in multi_value_keys {
+            if let Entry::Occupied(entry) = headers.entry(k) {
+                let (k, values) = entry.remove_entry_mult();
+
+                for v in values {
+                    multi_value_headers.append(&k, v);
+                }
+            }
+        }
+
+        Ok(ApiResponseParts {
+            status_code: self.code.status_code().into(),
             headers,
-            body: Some(body),
+            multi_value_headers,
+            body,
+            is_base64_encoded,
+        })
+    }
+
+    pub fn try_into_proxy_resp(self) -> Result<ApiGatewayProxyResponse, ApiResponseError> {
+        Ok(self.try_into_parts()?.into())
+    }
+
+    #[cfg(feature = "http_api")]
+    pub fn try_into_http_api_resp(self) -> Result<ApiGatewayV2httpResponse, ApiResponseError> {
+        self.try_into_parts()?.try_into()
+    }
+
+    #[cfg(feature = "alb")]
+    pub fn try_into_alb_resp(self) -> Result<AlbTargetGroupResponse, ApiResponseError> {
+        Ok(self.try_into_parts()?.into())
+    }
+}
+
+impl From<ApiResponseParts> for ApiGatewayProxyResponse {
+    fn from(parts: ApiResponseParts) -> Self {
+        Self {
+            status_code: parts.status_code,
+            headers: parts.headers,
+            multi_value_headers: parts.multi_value_headers,
+            body: parts.body,
+            is_base64_encoded: parts.is_base64_encoded,
+        }
+    }
+}
+
+#[cfg(feature = "http_api")]
+impl TryFrom<ApiResponseParts> for ApiGatewayV2httpResponse {
+    type Error = ApiResponseError;
+
+    fn try_from(mut parts: ApiResponseParts) -> Result<Self, Self::Error> {
+        // HTTP API has no multi value headers, so cookies have their own field and the other
+        // headers having more than 1 value are joined with commas
+        let cookies = parts
+            .multi_value_headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .chain(parts.headers.get_all(SET_COOKIE))
+            .map(|cookie| {
+                cookie
+                    .to_str()
+                    .map(str::to_string)
+                    .map_err(|_| ApiResponseError::Header(SET_COOKIE))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Removes every value, while only the first one is returned
+        parts.multi_value_headers.remove(SET_COOKIE);
+        parts.headers.remove(SET_COOKIE);
+
+        for k in parts.multi_value_headers.keys() {
+            let v = parts
+                .multi_value_headers
+                .get_all(k)
+                .iter()
+                .map(HeaderValue::as_bytes)
+                .collect::<Vec<_>>()
+                .join(&b", "[..]);
+
+            let v = HeaderValue::from_bytes(&v).map_err(|_| ApiResponseError::Header(k.clone()))?;
+
+            parts.headers.insert(k, v);
+        }
+
+        Ok(Self {
+            status_code: parts.status_code,
+            headers: parts.headers,
+            body: parts.body,
+            is_base64_encoded: parts.is_base64_encoded,
+            cookies,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(feature = "alb")]
+impl From<ApiResponseParts> for AlbTargetGroupResponse {
+    fn from(parts: ApiResponseParts) -> Self {
+        let status_description = StatusCode::from_u16(parts.status_code as _)
+            .ok()
+            .and_then(|status_code| status_code.canonical_reason())
+            .map(|reason| format!("{} {reason}", parts.status_code));
+
+        // A target group reads either headers or multi_value_headers depending on whether its multi
+        // value headers are enabled, so multi_value_headers has to carry every header
+        let mut multi_value_headers = parts.headers.clone();
+
+        for (k, v) in &parts.multi_value_headers {
+            multi_value_headers.append(k, v.clone());
+        }
+
+        Self {
+            status_code: parts.status_code,
+            status_description,
+            headers: parts.headers,
+            multi_value_headers,
+            body: parts.body,
+            is_base64_encoded: parts.is_base64_encoded,
+        }
+    }
+}
+
+impl From<ApiResponse<'_>> for ApiGatewayProxyResponse {
+    fn from(api_resp: ApiResponse<'_>) -> Self {
+        let request_id = api_resp.request_id;
+
+        api_resp
+            .try_into_parts()
+            .unwrap_or_else(|err| ApiResponseParts::fallback(request_id, err))
+            .into()
+    }
+}
+
+#[cfg(feature = "http_api")]
+impl From<ApiResponse<'_>> for ApiGatewayV2httpResponse {
+    fn from(api_resp: ApiResponse<'_>) -> Self {
+        let request_id = api_resp.request_id;
+
+        api_resp.try_into_http_api_resp().unwrap_or_else(|err| {
+            ApiResponseParts::fallback(request_id, err)
+                .try_into()
+                .unwrap_or_else(|_| Self {
+                    status_code: ErrorCode::from(CommonErrorCode::InternalServerError)
+                        .status_code()
+                        .into(),
+                    ..Default::default()
+                })
+        })
+    }
+}
+
+#[cfg(feature = "alb")]
+impl From<ApiResponse<'_>> for AlbTargetGroupResponse {
+    fn from(api_resp: ApiResponse<'_>) -> Self {
+        let request_id = api_resp.request_id;
+
+        api_resp
+            .try_into_parts()
+            .unwrap_or_else(|err| ApiResponseParts::fallback(request_id, err))
+            .into()
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiResponseError {
+    Body(serde_json::Error),
+    Header(HeaderName),
+    Compression(io::Error),
+    Cookie(CookieError),
+}
+
+impl Display for ApiResponseError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Body(err) => write!(fmt, "Failed to serialize the response body: {err}"),
+            Self::Header(name) => write!(fmt, "Failed to convert the response header: {name}"),
+            Self::Compression(err) => write!(fmt, "Failed to compress the response body: {err}"),
+            Self::Cookie(err) => write!(fmt, "Failed to set the response cookie: {err}"),
+        }
+    }
+}
+
+impl Error for ApiResponseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Body(err) => Some(err),
+            Self::Header(_) => None,
+            Self::Compression(err) => Some(err),
+            Self::Cookie(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+fn compress(body: &[u8], content_encoding: ContentEncoding) -> io::Result<Vec<u8>> {
+    match content_encoding {
+        ContentEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        ContentEncoding::Br => {
+            let mut compressed_body = Vec::new();
+            let mut encoder = CompressorWriter::new(&mut compressed_body, 4096, 5, 22);
+            encoder.write_all(body)?;
+            drop(encoder);
+            Ok(compressed_body)
+        }
+    }
+}
+
+// Without the codecs, ContentEncoding::negotiate never picks an encoding for this to be called
+#[cfg(not(feature = "compression"))]
+fn compress(body: &[u8], _content_encoding: ContentEncoding) -> io::Result<Vec<u8>> {
+    Ok(body.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resp_with_cookies() -> ApiResponse<'static> {
+        ApiResponse {
+            request_id: "request_id",
             ..Default::default()
         }
+        .with_cookie(&Cookie::new("session", "a"))
+        .unwrap()
+        .with_cookie(&Cookie::new("refresh", "b"))
+        .unwrap()
+    }
+
+    #[test]
+    fn proxy_resp_splits_single_and_multi_value_headers() {
+        let resp = resp_with_cookies().try_into_proxy_resp().unwrap();
+
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(resp.headers[CONTENT_TYPE], "application/json");
+        assert!(!resp.headers.contains_key(SET_COOKIE));
+        assert_eq!(
+            resp.multi_value_headers.get_all(SET_COOKIE).iter().count(),
+            2
+        );
+    }
+
+    #[cfg(feature = "alb")]
+    #[test]
+    fn alb_resp_has_every_header_in_multi_value_headers() {
+        let resp = resp_with_cookies().try_into_alb_resp().unwrap();
+
+        assert_eq!(resp.headers[CONTENT_TYPE], "application/json");
+        assert_eq!(resp.multi_value_headers[CONTENT_TYPE], "application/json");
+        assert_eq!(
+            resp.multi_value_headers.get_all(SET_COOKIE).iter().count(),
+            2
+        );
+        assert_eq!(resp.status_description.as_deref(), Some("200 OK"));
+    }
+
+    #[cfg(feature = "http_api")]
+    #[test]
+    fn http_api_resp_moves_cookies_to_their_own_field() {
+        let resp = resp_with_cookies().try_into_http_api_resp().unwrap();
+
+        assert_eq!(resp.cookies, ["session=a", "refresh=b"]);
+        assert!(!resp.headers.contains_key(SET_COOKIE));
+    }
+
+    #[test]
+    fn invalid_header_falls_back_to_internal_server_error() {
+        let resp = ApiResponse {
+            body: ResponseBody::Text {
+                content_type: "text/plain\n".to_string(),
+                text: "".to_string(),
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(ApiGatewayProxyResponse::from(resp).status_code, 500);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn handler_encoded_body_is_not_compressed_again() {
+        let text = "a".repeat(MIN_COMPRESSED_BODY_LEN);
+        let mut resp = ApiResponse {
+            body: ResponseBody::Text {
+                content_type: "text/plain".to_string(),
+                text: text.clone(),
+            },
+            content_encoding: Some(ContentEncoding::Gzip),
+            ..Default::default()
+        };
+
+        resp.headers
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+
+        let resp = resp.try_into_proxy_resp().unwrap();
+
+        assert_eq!(resp.headers[CONTENT_ENCODING], "br");
+        assert!(!resp.headers.contains_key(VARY));
+        assert_eq!(resp.body, Some(Body::Binary(text.into_bytes())));
+    }
+
+    #[test]
+    fn invalid_cookie_is_not_set() {
+        let err = ApiResponse::default()
+            .with_cookie(&Cookie::new("session", "a; Domain=example.com"))
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            ApiResponseError::Cookie(CookieError::Value(name)) if name == "session"
+        ));
     }
 }
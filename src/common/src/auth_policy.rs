@@ -0,0 +1,273 @@
+use crate::{common_enums::Method, MethodArn};
+use aws_lambda_events::{
+    apigw::{ApiGatewayCustomAuthorizerPolicy, ApiGatewayCustomAuthorizerResponse},
+    iam::{IamPolicyEffect, IamPolicyStatement},
+};
+use serde_json::{Map, Value};
+
+const POLICY_VERSION: &str = "2012-10-17";
+const INVOKE_ACTION: &str = "execute-api:Invoke";
+
+// Builds the IAM policy returned by a Lambda authorizer, e.g.
+// AuthPolicy::new(&method_arn, user_id)
+//     .allow(Method::Get, "users/*")
+//     .context("role", "admin")
+//     .build()
+#[derive(Debug, PartialEq)]
+pub struct AuthPolicy<'a> {
+    method_arn: &'a MethodArn,
+    principal_id: String,
+    allowed_resources: Vec<String>,
+    denied_resources: Vec<String>,
+    context: Map<String, Value>,
+    usage_identifier_key: Option<String>,
+}
+
+impl<'a> AuthPolicy<'a> {
+    pub fn new(method_arn: &'a MethodArn, principal_id: &str) -> Self {
+        Self {
+            method_arn,
+            principal_id: principal_id.to_string(),
+            allowed_resources: vec![],
+            denied_resources: vec![],
+            context: Map::new(),
+            usage_identifier_key: None,
+        }
+    }
+
+    // Allows every method and path of the stage being called
+    pub fn allow_all(self) -> Self {
+        self.allow(Method::Any, "*")
+    }
+
+    pub fn deny_all(self) -> Self {
+        self.deny(Method::Any, "*")
+    }
+
+    // The path can end with `*` to match a path prefix, e.g. users/*
+    pub fn allow(mut self, method: Method, path: &str) -> Self {
+        let resource = self.resource(method, path);
+        self.allowed_resources.push(resource);
+        self
+    }
+
+    pub fn deny(mut self, method: Method, path: &str) -> Self {
+        let resource = self.resource(method, path);
+        self.denied_resources.push(resource);
+        self
+    }
+
+    pub fn context(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.context.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn usage_identifier_key(mut self, usage_identifier_key: &str) -> Self {
+        self.usage_identifier_key = Some(usage_identifier_key.to_string());
+        self
+    }
+
+    pub fn build(self) -> ApiGatewayCustomAuthorizerResponse {
+        let all_resources = self.resource(Method::Any, "*");
+        let mut statement = vec![];
+
+        if !self.allowed_resources.is_empty() {
+            statement.push(IamPolicyStatement {
+                action: vec![INVOKE_ACTION.to_string()],
+                effect: IamPolicyEffect::Allow,
+                resource: self.allowed_resources,
+                condition: None,
+            });
+        }
+
+        // Deny everything when nothing is allowed or denied, as API Gateway rejects an empty policy
+        if !self.denied_resources.is_empty() || statement.is_empty() {
+            let denied_resources = if self.denied_resources.is_empty() {
+                vec![all_resources]
+            } else {
+                self.denied_resources
+            };
+
+            statement.push(IamPolicyStatement {
+                action: vec![INVOKE_ACTION.to_string()],
+                effect: IamPolicyEffect::Deny,
+                resource: denied_resources,
+                condition: None,
+            });
+        }
+
+        // API Gateway only accepts strings, numbers and booleans in the context
+        let context = self
+            .context
+            .into_iter()
+            .map(|(k, v)| match v {
+                Value::Array(_) | Value::Object(_) => (k, Value::String(v.to_string())),
+                v => (k, v),
+            })
+            .collect();
+
+        ApiGatewayCustomAuthorizerResponse {
+            principal_id: Some(self.principal_id),
+            policy_document: ApiGatewayCustomAuthorizerPolicy {
+                version: Some(POLICY_VERSION.to_string()),
+                statement,
+            },
+            context: Value::Object(context),
+            usage_identifier_key: self.usage_identifier_key,
+        }
+    }
+
+    fn resource(&self, method: Method, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+
+        MethodArn {
+            method,
+            path: Some(if path.is_empty() {
+                vec![]
+            } else {
+                path.split('/').map(str::to_string).collect()
+            }),
+            ..self.method_arn.clone()
+        }
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const METHOD_ARN: &str =
+        "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET/users/123";
+
+    fn method_arn() -> MethodArn {
+        METHOD_ARN.parse().unwrap()
+    }
+
+    fn resources(resp: &ApiGatewayCustomAuthorizerResponse, effect: IamPolicyEffect) -> Vec<&str> {
+        resp.policy_document
+            .statement
+            .iter()
+            .filter(|statement| statement.effect == effect)
+            .flat_map(|statement| statement.resource.iter().map(String::as_str))
+            .collect()
+    }
+
+    #[test]
+    fn allowed_and_denied_resources_are_listed() {
+        let method_arn = method_arn();
+
+        let resp = AuthPolicy::new(&method_arn, "user")
+            .allow(Method::Get, "/users/*")
+            .allow(Method::Any, "posts")
+            .allow(Method::Post, "/")
+            .deny(Method::Delete, "users/1")
+            .build();
+
+        assert_eq!(
+            resources(&resp, IamPolicyEffect::Allow),
+            [
+                "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET/users/*",
+                "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/*/posts",
+                "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/POST/",
+            ]
+        );
+        assert_eq!(
+            resources(&resp, IamPolicyEffect::Deny),
+            ["arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/DELETE/users/1"]
+        );
+    }
+
+    #[test]
+    fn allow_all_and_deny_all_cover_the_stage() {
+        let method_arn = method_arn();
+        let all_resources = "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/*/*";
+
+        let resp = AuthPolicy::new(&method_arn, "user").allow_all().build();
+
+        assert_eq!(resources(&resp, IamPolicyEffect::Allow), [all_resources]);
+        assert!(resources(&resp, IamPolicyEffect::Deny).is_empty());
+
+        let resp = AuthPolicy::new(&method_arn, "user").deny_all().build();
+
+        assert!(resources(&resp, IamPolicyEffect::Allow).is_empty());
+        assert_eq!(resources(&resp, IamPolicyEffect::Deny), [all_resources]);
+    }
+
+    #[test]
+    fn empty_policy_denies_everything() {
+        let method_arn = method_arn();
+        let resp = AuthPolicy::new(&method_arn, "user").build();
+
+        assert_eq!(resp.policy_document.statement.len(), 1);
+        assert_eq!(
+            resources(&resp, IamPolicyEffect::Deny),
+            ["arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/*/*"]
+        );
+    }
+
+    #[test]
+    fn context_values_other_than_scalars_are_stringified() {
+        let method_arn = method_arn();
+
+        let resp = AuthPolicy::new(&method_arn, "user")
+            .context("role", "admin")
+            .context("level", 3)
+            .context("is_verified", true)
+            .context("scopes", json!(["read", "write"]))
+            .context("profile", json!({ "name": "Alice" }))
+            .build();
+
+        assert_eq!(
+            resp.context,
+            json!({
+                "role": "admin",
+                "level": 3,
+                "is_verified": true,
+                "scopes": r#"["read","write"]"#,
+                "profile": r#"{"name":"Alice"}"#,
+            })
+        );
+    }
+
+    #[test]
+    fn response_serializes_to_the_authorizer_format() {
+        let method_arn = method_arn();
+
+        let resp = AuthPolicy::new(&method_arn, "user")
+            .allow(Method::Get, "users/*")
+            .deny(Method::Delete, "users/1")
+            .context("role", "admin")
+            .usage_identifier_key("api key")
+            .build();
+
+        assert_eq!(
+            serde_json::to_value(resp).unwrap(),
+            json!({
+                "principalId": "user",
+                "policyDocument": {
+                    "Version": "2012-10-17",
+                    "Statement": [
+                        {
+                            "Action": ["execute-api:Invoke"],
+                            "Effect": "Allow",
+                            "Resource": [
+                                "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET/users/*",
+                            ],
+                        },
+                        {
+                            "Action": ["execute-api:Invoke"],
+                            "Effect": "Deny",
+                            "Resource": [
+                                "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/DELETE/users/1",
+                            ],
+                        },
+                    ],
+                },
+                "context": { "role": "admin" },
+                "usageIdentifierKey": "api key",
+            })
+        );
+    }
+}
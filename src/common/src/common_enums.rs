@@ -5,6 +5,293 @@ use serde::Deserialize;
 pub enum Method {
     Post,
     Put,
+    Patch,
     Get,
+    Head,
+    Options,
     Delete,
+
+    // Any method, as used by method ARNs and IAM policies
+    #[serde(rename = "*")]
+    Any,
+}
+
+impl Method {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Patch => "PATCH",
+            Self::Get => "GET",
+            Self::Head => "HEAD",
+            Self::Options => "OPTIONS",
+            Self::Delete => "DELETE",
+            Self::Any => "*",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "POST" => Some(Self::Post),
+            "PUT" => Some(Self::Put),
+            "PATCH" => Some(Self::Patch),
+            "GET" => Some(Self::Get),
+            "HEAD" => Some(Self::Head),
+            "OPTIONS" => Some(Self::Options),
+            "DELETE" => Some(Self::Delete),
+            "*" => Some(Self::Any),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RequestSource {
+    Body,
+    Path,
+    Query,
+}
+
+impl RequestSource {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Body => "body",
+            Self::Path => "path",
+            Self::Query => "query",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MergePolicy {
+    BodyWins,
+
+    #[default]
+    PathWins,
+
+    RejectOnConflict,
+}
+
+impl MergePolicy {
+    // Sources ordered from the lowest to the highest precedence
+    pub const fn precedence(&self) -> [RequestSource; 3] {
+        match self {
+            Self::BodyWins => [
+                RequestSource::Query,
+                RequestSource::Path,
+                RequestSource::Body,
+            ],
+            Self::PathWins | Self::RejectOnConflict => [
+                RequestSource::Body,
+                RequestSource::Query,
+                RequestSource::Path,
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContentEncoding {
+    Gzip,
+    Br,
+}
+
+impl ContentEncoding {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Br => "br",
+        }
+    }
+
+    // Picks the supported encoding the client prefers the most according to its q-values, where
+    // br wins a tie because it compresses better
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let supported_encodings: &[Self] = if cfg!(feature = "compression") {
+            &[Self::Br, Self::Gzip]
+        } else {
+            &[]
+        };
+
+        let accepted_encodings = accept_encoding
+            .split(',')
+            .map(|accepted_encoding| {
+                let mut parts = accepted_encoding.split(';');
+                let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+
+                let q = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+
+                (name, q)
+            })
+            .collect::<Vec<_>>();
+
+        supported_encodings
+            .iter()
+            .filter_map(|&encoding| {
+                accepted_encodings
+                    .iter()
+                    .find(|(name, _)| name == encoding.as_str())
+                    .or_else(|| accepted_encodings.iter().find(|(name, _)| name == "*"))
+                    .map(|&(_, q)| (encoding, q))
+            })
+            .filter(|&(_, q)| q > 0.0)
+            .fold(
+                None,
+                |best: Option<(Self, f32)>, (encoding, q)| match best {
+                    Some((_, best_q)) if best_q >= q => best,
+                    _ => Some((encoding, q)),
+                },
+            )
+            .map(|(encoding, _)| encoding)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum JwtAlgorithm {
+    Hs256,
+    Rs256,
+    Es256,
+    EdDsa,
+}
+
+impl JwtAlgorithm {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hs256 => "HS256",
+            Self::Rs256 => "RS256",
+            Self::Es256 => "ES256",
+            Self::EdDsa => "EdDSA",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OtpAlgorithm {
+    // The only one every authenticator app supports
+    #[default]
+    Sha1,
+
+    Sha256,
+    Sha512,
+}
+
+impl OtpAlgorithm {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+            Self::Sha512 => "SHA512",
+        }
+    }
+}
+
+// Recognizes sensitive values by their shape, whatever key they are under
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValueDetector {
+    Jwt,
+    CardNumber,
+    Email,
+}
+
+impl ValueDetector {
+    pub fn is_detected(&self, value: &str) -> bool {
+        match self {
+            Self::Jwt => {
+                let value = value.trim();
+                let value = value.strip_prefix("Bearer ").unwrap_or(value);
+                let segments = value.split('.').collect::<Vec<_>>();
+
+                // The header always starts with `{"` which is base64url encoded into eyJ
+                segments.len() == 3
+                    && segments[0].starts_with("eyJ")
+                    && segments[1].len() > 1
+                    && segments.iter().all(|segment| {
+                        segment.bytes().all(|byte| {
+                            byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'
+                        })
+                    })
+            }
+            Self::CardNumber => {
+                if !value
+                    .bytes()
+                    .all(|byte| byte.is_ascii_digit() || byte == b' ' || byte == b'-')
+                {
+                    return false;
+                }
+
+                let digits = value
+                    .bytes()
+                    .filter(u8::is_ascii_digit)
+                    .map(|digit| u32::from(digit - b'0'))
+                    .collect::<Vec<_>>();
+
+                // Luhn checksum, which rules out most other numbers of the same length
+                (13..=19).contains(&digits.len())
+                    && digits
+                        .iter()
+                        .rev()
+                        .enumerate()
+                        .map(|(i, &digit)| match (i % 2, digit * 2) {
+                            (0, _) => digit,
+                            (_, doubled) if doubled > 9 => doubled - 9,
+                            (_, doubled) => doubled,
+                        })
+                        .sum::<u32>()
+                        % 10
+                        == 0
+            }
+            Self::Email => {
+                let Some((local_part, domain)) = value.trim().rsplit_once('@') else {
+                    return false;
+                };
+
+                !local_part.is_empty()
+                    && !value.trim().contains(char::is_whitespace)
+                    && domain
+                        .split_once('.')
+                        .is_some_and(|(name, tld)| !name.is_empty() && !tld.is_empty())
+            }
+        }
+    }
+}
+
+// How a sensitive value gets masked, where every strategy but Remove keeps the key in place so that
+// logs still show the value was there
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MaskStrategy {
+    #[default]
+    Remove,
+
+    // "***"
+    Replace,
+
+    // "***1234", where the last 4 chars are only shown for values long enough to keep most of them
+    // hidden, e.g. card numbers
+    LastFour,
+
+    // "********", with as many `*` as there are chars in the value
+    PreserveLength,
+
+    // "hmac:..." which is the same for the same value, so that it can be followed across log lines
+    Fingerprint,
 }
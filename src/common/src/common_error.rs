@@ -1,6 +1,4 @@
-use crate::ApiResponse;
-use aws_lambda_events::http::HeaderMap;
-use serde_json::json;
+use crate::{error_code::ErrorCode, ApiResponse};
 use std::{
     error::Error,
     fmt::{self, Display, Formatter},
@@ -8,7 +6,7 @@ use std::{
 
 #[derive(Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct CommonError {
-    pub code: u32,
+    pub code: ErrorCode,
     pub message: String,
 }
 
@@ -16,10 +14,9 @@ impl CommonError {
     pub fn into_api_resp(self, request_id: &str) -> ApiResponse<'_> {
         ApiResponse {
             code: self.code,
-            headers: HeaderMap::new(),
             message: self.message,
-            payload: json!({}),
             request_id,
+            ..Default::default()
         }
     }
 }
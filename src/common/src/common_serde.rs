@@ -1,28 +1,377 @@
-use anyhow::Result;
-use aws_lambda_events::apigw::ApiGatewayProxyRequest;
+use crate::{
+    api_request::ApiRequest,
+    common_enums::{MergePolicy, RequestSource},
+    error_code::CommonErrorCode,
+    request_body,
+    request_de::{self, RequestDeserializer, RequestField},
+    ApiResponse,
+};
+use base64::DecodeError;
 use serde::de::DeserializeOwned;
-use serde_json::{json, Map, Value};
-use validator::Validate;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+use validator::{Validate, ValidationErrors};
 
 pub trait Request
 where
     Self: DeserializeOwned + Validate,
 {
-    fn load(event: &ApiGatewayProxyRequest) -> Result<Self> {
-        let mut event_body = serde_json::from_str::<Map<String, Value>>(
-            event.body.as_ref().unwrap_or(&"{}".to_string()),
-        )?;
+    const MERGE_POLICY: MergePolicy = MergePolicy::PathWins;
 
-        for (k, v) in &event.path_parameters {
-            event_body.insert(k.to_string(), json!(v));
+    // Fields that can only come from the given sources, e.g. [("user_id", &[RequestSource::Path])],
+    // usually generated with #[derive(Request)] and #[request(source(path))] on the field
+    const FIELD_SOURCES: &'static [(&'static str, &'static [RequestSource])] = &[];
+
+    fn load(event: &impl ApiRequest) -> Result<Self, LoadError> {
+        // A misspelt or renamed field would otherwise silently accept every source
+        if let Some(field_names) = request_de::get_field_names::<Self>() {
+            if let Some(&(field, _)) = Self::FIELD_SOURCES
+                .iter()
+                .find(|(field, _)| !field_names.contains(field))
+            {
+                return Err(LoadError::UnknownField(field));
+            }
         }
 
-        for (k, v) in event.query_string_parameters.iter() {
-            event_body.insert(k.to_string(), json!(v));
+        let event_body = request_body::parse(event)?;
+
+        let path_parameters = event
+            .path_parameters()
+            .iter()
+            .map(|(k, v)| (k.to_string(), RequestField::Param(vec![v.to_string()])))
+            .collect::<Vec<_>>();
+
+        // Repeated query keys are kept together so that they can be loaded into a Vec field
+        let mut query_string_parameters = HashMap::<String, Vec<String>>::new();
+
+        for (k, v) in event.query_string_parameters().iter() {
+            query_string_parameters
+                .entry(k.to_string())
+                .or_default()
+                .push(v.to_string());
+        }
+
+        let query_string_parameters = query_string_parameters
+            .into_iter()
+            .map(|(k, v)| (k, RequestField::Param(v)))
+            .collect::<Vec<_>>();
+
+        let mut sources = [
+            (RequestSource::Body, event_body),
+            (RequestSource::Path, path_parameters),
+            (RequestSource::Query, query_string_parameters),
+        ];
+
+        sources.sort_by_key(|(source, _)| {
+            Self::MERGE_POLICY
+                .precedence()
+                .iter()
+                .position(|precedence_source| precedence_source == source)
+        });
+
+        // Later sources override the earlier ones according to the merge policy
+        let mut merged_event_body = HashMap::new();
+
+        for (source, values) in sources {
+            for (k, v) in values {
+                let allowed_sources = Self::FIELD_SOURCES
+                    .iter()
+                    .find(|&&(field, _)| field == k)
+                    .map(|&(_, allowed_sources)| allowed_sources);
+
+                if allowed_sources.is_some_and(|allowed_sources| !allowed_sources.contains(&source))
+                {
+                    return Err(LoadError::Source { field: k, source });
+                }
+
+                if Self::MERGE_POLICY == MergePolicy::RejectOnConflict
+                    && merged_event_body.contains_key(&k)
+                {
+                    return Err(LoadError::Conflict(k));
+                }
+
+                merged_event_body.insert(k, v);
+            }
         }
 
-        let req = serde_json::from_value::<Self>(json!(event_body))?;
-        req.validate()?;
+        let req = Self::deserialize(RequestDeserializer(merged_event_body))
+            .map_err(LoadError::Deserialize)?;
+
+        req.validate().map_err(LoadError::Validation)?;
         Ok(req)
     }
 }
+
+#[derive(Debug)]
+pub enum LoadError {
+    Base64(DecodeError),
+    Body(serde_json::Error),
+    Multipart(String),
+    UnsupportedMediaType(String),
+    Deserialize(serde_json::Error),
+    Validation(ValidationErrors),
+    Conflict(String),
+    Source {
+        field: String,
+        source: RequestSource,
+    },
+    UnknownField(&'static str),
+}
+
+impl LoadError {
+    pub fn into_api_resp(self, request_id: &str) -> ApiResponse<'_> {
+        let (code, message, payload) = match self {
+            Self::Base64(err) => (CommonErrorCode::InvalidBody, err.to_string(), json!({})),
+            Self::Body(err) => (CommonErrorCode::InvalidBody, err.to_string(), json!({})),
+            Self::Multipart(message) => (CommonErrorCode::InvalidBody, message, json!({})),
+            Self::UnsupportedMediaType(media_type) => (
+                CommonErrorCode::UnsupportedMediaType,
+                "".to_string(),
+                json!({ "content_type": media_type }),
+            ),
+            Self::Deserialize(err) => (CommonErrorCode::InvalidRequest, err.to_string(), json!({})),
+            // Nested structs and lists are kept as nested objects keyed by field name and index
+            Self::Validation(errs) => (
+                CommonErrorCode::ValidationFailed,
+                "".to_string(),
+                json!({ "errors": errs }),
+            ),
+            Self::Conflict(field) => (
+                CommonErrorCode::ConflictingField,
+                "".to_string(),
+                json!({ "field": field }),
+            ),
+            Self::Source { field, source } => (
+                CommonErrorCode::FieldSourceNotAllowed,
+                "".to_string(),
+                json!({ "field": field, "source": source.as_str() }),
+            ),
+            Self::UnknownField(field) => (
+                CommonErrorCode::InternalServerError,
+                "".to_string(),
+                json!({ "field": field }),
+            ),
+        };
+
+        ApiResponse {
+            code: code.into(),
+            message,
+            payload,
+            request_id,
+            ..Default::default()
+        }
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Base64(err) => write!(fmt, "Failed to decode the request body: {err}"),
+            Self::Body(err) => write!(fmt, "Failed to parse the request body: {err}"),
+            Self::Multipart(message) => {
+                write!(fmt, "Failed to parse the multipart request body: {message}")
+            }
+            Self::UnsupportedMediaType(media_type) => {
+                write!(fmt, "Unsupported request content type: {media_type}")
+            }
+            Self::Deserialize(err) => write!(fmt, "Failed to deserialize the request: {err}"),
+            Self::Validation(errs) => write!(fmt, "Failed to validate the request: {errs}"),
+            Self::Conflict(field) => write!(fmt, "Field {field} comes from more than 1 source"),
+            Self::Source { field, source } => {
+                write!(fmt, "Field {field} cannot come from {}", source.as_str())
+            }
+            Self::UnknownField(field) => {
+                write!(
+                    fmt,
+                    "Field {field} in FIELD_SOURCES is not a field of the request"
+                )
+            }
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Base64(err) => Some(err),
+            Self::Body(err) | Self::Deserialize(err) => Some(err),
+            Self::Validation(errs) => Some(errs),
+            Self::Multipart(_)
+            | Self::UnsupportedMediaType(_)
+            | Self::Conflict(_)
+            | Self::Source { .. }
+            | Self::UnknownField(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Request;
+    use aws_lambda_events::apigw::ApiGatewayProxyRequest;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, Request)]
+    #[serde(rename_all = "camelCase")]
+    #[request(merge_policy = "reject_on_conflict")]
+    struct UpdateUserRequest {
+        #[request(source(path))]
+        user_id: String,
+
+        #[serde(rename(deserialize = "name"))]
+        #[request(source(body, query))]
+        display_name: String,
+    }
+
+    impl Validate for UpdateUserRequest {
+        fn validate(&self) -> Result<(), ValidationErrors> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct MisspeltRequest {
+        #[allow(dead_code)]
+        user_id: String,
+    }
+
+    impl Validate for MisspeltRequest {
+        fn validate(&self) -> Result<(), ValidationErrors> {
+            Ok(())
+        }
+    }
+
+    impl Request for MisspeltRequest {
+        const FIELD_SOURCES: &'static [(&'static str, &'static [RequestSource])] =
+            &[("userId", &[RequestSource::Path])];
+    }
+
+    fn event(path_parameters: &[(&str, &str)], query: &[(&str, &str)]) -> ApiGatewayProxyRequest {
+        ApiGatewayProxyRequest {
+            path_parameters: path_parameters
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            query_string_parameters: query
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+                .into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn derive_generates_field_sources_under_deserialized_names() {
+        assert_eq!(
+            UpdateUserRequest::MERGE_POLICY,
+            MergePolicy::RejectOnConflict
+        );
+        assert_eq!(
+            UpdateUserRequest::FIELD_SOURCES,
+            &[
+                ("userId", &[RequestSource::Path][..]),
+                ("name", &[RequestSource::Body, RequestSource::Query][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_applies_field_sources() {
+        let req = UpdateUserRequest::load(&event(&[("userId", "1")], &[("name", "Ann")])).unwrap();
+
+        assert_eq!(req.user_id, "1");
+        assert_eq!(req.display_name, "Ann");
+
+        let err =
+            UpdateUserRequest::load(&event(&[], &[("userId", "1"), ("name", "Ann")])).unwrap_err();
+
+        assert!(matches!(
+            err,
+            LoadError::Source { field, source: RequestSource::Query } if field == "userId"
+        ));
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct SignUpRequest {
+        #[validate(length(min = 3))]
+        username: String,
+
+        #[validate(nested)]
+        address: Address,
+
+        #[validate(nested)]
+        contacts: Vec<Contact>,
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Address {
+        #[validate(length(min = 1, message = "Postcode is required"))]
+        postcode: String,
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Contact {
+        #[validate(email)]
+        email: String,
+    }
+
+    impl Request for SignUpRequest {}
+
+    #[test]
+    fn validation_errors_are_nested_by_field_and_index() {
+        let event = ApiGatewayProxyRequest {
+            body: Some(
+                json!({
+                    "username": "al",
+                    "address": { "postcode": "" },
+                    "contacts": [{ "email": "a@example.com" }, { "email": "a" }],
+                })
+                .to_string(),
+            ),
+            ..Default::default()
+        };
+
+        let resp = SignUpRequest::load(&event)
+            .unwrap_err()
+            .into_api_resp("request id");
+
+        assert_eq!(resp.code, CommonErrorCode::ValidationFailed.into());
+        assert_eq!(
+            resp.payload,
+            json!({
+                "errors": {
+                    "username": [
+                        { "code": "length", "message": null, "params": { "min": 3, "value": "al" } },
+                    ],
+                    "address": {
+                        "postcode": [{
+                            "code": "length",
+                            "message": "Postcode is required",
+                            "params": { "min": 1, "value": "" },
+                        }],
+                    },
+                    "contacts": {
+                        "1": {
+                            "email": [
+                                { "code": "email", "message": null, "params": { "value": "a" } },
+                            ],
+                        },
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn load_rejects_field_sources_naming_no_field() {
+        let err = MisspeltRequest::load(&event(&[("user_id", "1")], &[])).unwrap_err();
+
+        assert!(matches!(err, LoadError::UnknownField("userId")));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{constants, SensitiveData};
+use crate::Redacted;
 use aws_lambda_events::apigw::{
     ApiGatewayCustomAuthorizerRequest, ApiGatewayCustomAuthorizerRequestTypeRequest,
     ApiGatewayProxyRequest,
@@ -7,8 +7,14 @@ use lambda_runtime::tracing::{
     info,
     subscriber::{self, EnvFilter},
 };
-use serde_json::{json, Error, Map, Value};
-use std::mem;
+use serde::Serialize;
+use serde_json::{Error, Value};
+
+#[cfg(feature = "http_api")]
+use aws_lambda_events::apigw::ApiGatewayV2httpRequest;
+
+#[cfg(feature = "alb")]
+use aws_lambda_events::alb::AlbTargetGroupRequest;
 
 pub fn init() {
     subscriber::fmt()
@@ -24,100 +30,63 @@ pub fn init() {
         .init();
 }
 
+// Logs the event with its sensitive data masked, leaving the event itself untouched
 pub trait Logger {
-    fn log(&mut self) -> Result<(), Error>;
+    fn log(&self) -> Result<(), Error>;
 }
 
-impl Logger for ApiGatewayProxyRequest {
-    fn log(&mut self) -> Result<(), Error> {
-        // Hide sensitive input, assume caller no longer needs these keys for their logic
-        for &sensitive_key in constants::SENSITIVE_KEYS {
-            self.headers.remove(sensitive_key);
-            self.multi_value_headers.remove(sensitive_key);
-            self.path_parameters.remove(sensitive_key);
-        }
-        self.request_context.identity.api_key = None;
-        self.request_context.identity.api_key_id = None;
-        self.request_context.identity.access_key = None;
-
-        let event_body = serde_json::from_str::<Map<String, Value>>(
-            self.body.as_ref().unwrap_or(&"{}".to_string()),
-        )?;
+// Not sensitive by name, but none of them are needed to debug a request, where cookies carry the
+// session and refresh cookies of HTTP API events
+const EVENT_SENSITIVE_KEYS: &[&str] = &["apiKey", "apiKeyId", "accessKey", "cookies", "cookie"];
 
-        // Hide sensitive input, but later need to reveal back for caller logic
-        let mut sensitive_event_body = SensitiveData::new(event_body).call();
-        sensitive_event_body.hide();
-        self.body = Some(serde_json::to_string(sensitive_event_body.get())?);
+// The body of API Gateway and ALB events is a JSON string, which gets redacted as JSON, while other
+// bodies, e.g. form or base64 bodies, are replaced with a placeholder
+const EVENT_JSON_KEYS: &[&str] = &["body"];
 
-        // Log the event
-        info!(event = serde_json::to_string(self)?);
+impl Logger for ApiGatewayProxyRequest {
+    fn log(&self) -> Result<(), Error> {
+        log_redacted(self)
+    }
+}
 
-        // Reveal back the sensitive input for caller logic
-        sensitive_event_body.show();
-        self.body = Some(serde_json::to_string(sensitive_event_body.get())?);
+#[cfg(feature = "http_api")]
+impl Logger for ApiGatewayV2httpRequest {
+    fn log(&self) -> Result<(), Error> {
+        log_redacted(self)
+    }
+}
 
-        Ok(())
+#[cfg(feature = "alb")]
+impl Logger for AlbTargetGroupRequest {
+    fn log(&self) -> Result<(), Error> {
+        log_redacted(self)
     }
 }
 
 impl Logger for ApiGatewayCustomAuthorizerRequest {
-    fn log(&mut self) -> Result<(), Error> {
-        // Hide sensitive input, but later need to reveal back for caller logic
-        let auth_token = self.authorization_token.take();
-
-        // Log the event
-        info!(event = serde_json::to_string(self)?);
-
-        // Reveal back the sensitive input for caller logic
-        self.authorization_token = auth_token;
-
-        Ok(())
+    fn log(&self) -> Result<(), Error> {
+        log_redacted(self)
     }
 }
 
 impl Logger for ApiGatewayCustomAuthorizerRequestTypeRequest {
-    fn log(&mut self) -> Result<(), Error> {
-        // Hide sensitive input, assume caller no longer needs these keys for their logic
-        for &sensitive_key in constants::SENSITIVE_KEYS {
-            self.headers.remove(sensitive_key);
-            self.multi_value_headers.remove(sensitive_key);
-            self.path_parameters.remove(sensitive_key);
-        }
-        if let Some(identity) = &mut self.request_context.identity {
-            identity.api_key = None;
-            identity.api_key_id = None;
-        }
-
-        // Hide sensitive input, but later need to reveal back for caller logic
-        let auth_token = self.headers.remove("Authorization");
-
-        // Log the event
-        info!(event = serde_json::to_string(self)?);
-
-        // Reveal back the sensitive input for caller logic
-        if let Some(auth_token) = auth_token {
-            self.headers.append("Authorization", auth_token);
-        }
-
-        Ok(())
+    fn log(&self) -> Result<(), Error> {
+        log_redacted(self)
     }
 }
 
 impl Logger for Value {
-    fn log(&mut self) -> Result<(), Error> {
-        // Hide sensitive input, but later need to reveal back for caller logic
-        let mut sensitive_event_body =
-            SensitiveData::new(mem::take(self.as_object_mut().unwrap_or(&mut Map::new()))).call();
-
-        sensitive_event_body.hide();
-
-        // Log the event
-        info!(event = serde_json::to_string(sensitive_event_body.get())?);
+    fn log(&self) -> Result<(), Error> {
+        log_redacted(self)
+    }
+}
 
-        // Reveal back the sensitive input for caller logic
-        sensitive_event_body.show();
-        *self = json!(sensitive_event_body.into_data());
+fn log_redacted(event: &impl Serialize) -> Result<(), Error> {
+    let event = Redacted::new(event)
+        .extra_sensitive_keys(EVENT_SENSITIVE_KEYS)
+        .json_keys(EVENT_JSON_KEYS)
+        .call();
 
-        Ok(())
-    }
+    info!(event = serde_json::to_string(&event)?);
+    Ok(())
 }
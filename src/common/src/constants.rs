@@ -1,4 +1,4 @@
-use std::{collections::HashMap, env};
+use std::env;
 
 pub const SENSITIVE_KEYS: &[&str] = &[
     "postman-token",
@@ -14,6 +14,9 @@ pub const SENSITIVE_KEYS: &[&str] = &[
     "Authorization",
 ];
 
+// Matched case-insensitively against keys, e.g. user_password or client_secret
+pub const SENSITIVE_KEY_GLOBS: &[&str] = &["*password*", "*secret*", "*token*"];
+
 thread_local! {
     // Environment variables
     pub static LOG_LEVEL: String = env::var("LOG_LEVEL").unwrap();
@@ -21,15 +24,4 @@ thread_local! {
     pub static STAGE: String = env::var("STAGE").unwrap();
     pub static STAGE_PREFIX: String = env::var("STAGE_PREFIX").unwrap();
     pub static STAGE_DASH_PREFIX: String = env::var("STAGE_DASH_PREFIX").unwrap();
-
-    // API response error messages
-    pub static API_ERR_MSG_MAP: HashMap<i64, &'static str> =
-        HashMap::from_iter([
-            (400, "Bad request"),
-            (401, "Unauthorized"),
-            (403, "Forbidden"),
-            (404, "Data was not found"),
-            (409, "Conflict"),
-            (500, "Internal server error"),
-        ]);
 }
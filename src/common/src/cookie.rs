@@ -0,0 +1,272 @@
+use crate::{
+    common_enums::SameSite,
+    request_de::{RequestDeserializer, RequestField},
+};
+use aws_lambda_events::http::{header::COOKIE, HeaderMap};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub max_age: Option<i64>,
+    pub same_site: Option<SameSite>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Makes the browser delete the cookie with the same name, domain and path
+    pub fn removal(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            max_age: Some(0),
+            ..Default::default()
+        }
+    }
+
+    // Checks the cookie against the Set-Cookie grammar of RFC 6265, as the browser would otherwise
+    // drop or misread it, e.g. a `;` in the value starting a new attribute
+    pub fn validate(&self) -> Result<(), CookieError> {
+        if self.name.is_empty() || !self.name.bytes().all(is_token_octet) {
+            return Err(CookieError::Name(self.name.to_string()));
+        }
+
+        let value = self
+            .value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(&self.value);
+
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(CookieError::Value(self.name.to_string()));
+        }
+
+        if self
+            .domain
+            .iter()
+            .chain(&self.path)
+            .any(|attr| !attr.bytes().all(is_attr_octet))
+        {
+            return Err(CookieError::Attribute(self.name.to_string()));
+        }
+
+        // Browsers reject SameSite=None without Secure
+        if self.same_site == Some(SameSite::None) && !self.secure {
+            return Err(CookieError::InsecureSameSiteNone(self.name.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+// token = 1*<any CHAR except CTLs or separators>, from RFC 2616
+fn is_token_octet(octet: u8) -> bool {
+    octet.is_ascii_graphic() && !br#"()<>@,;:\"/[]?={}"#.contains(&octet)
+}
+
+// cookie-octet = %x21 / %x23-2B / %x2D-3A / %x3C-5B / %x5D-7E
+fn is_cookie_octet(octet: u8) -> bool {
+    octet.is_ascii_graphic() && !matches!(octet, b'"' | b',' | b';' | b'\\')
+}
+
+// av-octet = any CHAR except CTLs or ";"
+fn is_attr_octet(octet: u8) -> bool {
+    (octet == b' ' || octet.is_ascii_graphic()) && octet != b';'
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CookieError {
+    Name(String),
+    Value(String),
+    Attribute(String),
+    InsecureSameSiteNone(String),
+}
+
+impl Display for CookieError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(fmt, "Invalid cookie name: {name:?}"),
+            Self::Value(name) => write!(fmt, "Invalid value of the cookie {name}"),
+            Self::Attribute(name) => write!(fmt, "Invalid Domain or Path of the cookie {name}"),
+            Self::InsecureSameSiteNone(name) => {
+                write!(fmt, "The cookie {name} has SameSite=None but is not Secure")
+            }
+        }
+    }
+}
+
+impl Error for CookieError {}
+
+// Formats the cookie as a Set-Cookie header value, which is only well formed once validated
+impl Display for Cookie {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}={}", self.name, self.value)?;
+
+        if let Some(domain) = &self.domain {
+            write!(fmt, "; Domain={domain}")?;
+        }
+
+        if let Some(path) = &self.path {
+            write!(fmt, "; Path={path}")?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(fmt, "; Max-Age={max_age}")?;
+        }
+
+        if let Some(same_site) = self.same_site {
+            write!(fmt, "; SameSite={}", same_site.as_str())?;
+        }
+
+        if self.secure {
+            write!(fmt, "; Secure")?;
+        }
+
+        if self.http_only {
+            write!(fmt, "; HttpOnly")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CookieJar(HashMap<String, String>);
+
+impl CookieJar {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    // Loads the cookies into a struct, coercing each value into its field type
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        T::deserialize(RequestDeserializer(
+            self.0
+                .iter()
+                .map(|(k, v)| (k.to_string(), RequestField::Param(vec![v.to_string()])))
+                .collect(),
+        ))
+    }
+}
+
+// Each item is a `name=value` pair, e.g. an entry of the Cookie header or of the HTTP API cookies
+impl<'a> FromIterator<&'a str> for CookieJar {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(cookies: I) -> Self {
+        Self(
+            cookies
+                .into_iter()
+                .flat_map(|cookies| cookies.split(';'))
+                .filter_map(|cookie| cookie.split_once('='))
+                .map(|(k, v)| {
+                    let v = v.trim();
+                    let v = v
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .unwrap_or(v);
+
+                    (k.trim().to_string(), v.to_string())
+                })
+                .collect(),
+        )
+    }
+}
+
+impl From<&HeaderMap> for CookieJar {
+    fn from(headers: &HeaderMap) -> Self {
+        headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|cookies| cookies.to_str().ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_cookie_is_formatted_with_its_attributes() {
+        let cookie = Cookie {
+            path: Some("/".to_string()),
+            max_age: Some(60),
+            same_site: Some(SameSite::None),
+            secure: true,
+            http_only: true,
+            ..Cookie::new("session", "\"a1b2\"")
+        };
+
+        assert_eq!(cookie.validate(), Ok(()));
+        assert_eq!(
+            cookie.to_string(),
+            "session=\"a1b2\"; Path=/; Max-Age=60; SameSite=None; Secure; HttpOnly"
+        );
+    }
+
+    #[test]
+    fn invalid_name_or_value_is_rejected() {
+        for name in ["", "a b", "a=b", "a;b", "a\"b"] {
+            assert_eq!(
+                Cookie::new(name, "a").validate(),
+                Err(CookieError::Name(name.to_string()))
+            );
+        }
+
+        for value in ["a b", "a;b", "a,b", "a\\b", "a\"b", "\"a", "ä"] {
+            assert_eq!(
+                Cookie::new("session", value).validate(),
+                Err(CookieError::Value("session".to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn attribute_cannot_start_another_attribute() {
+        let cookie = Cookie {
+            path: Some("/; Domain=example.com".to_string()),
+            ..Cookie::new("session", "a")
+        };
+
+        assert_eq!(
+            cookie.validate(),
+            Err(CookieError::Attribute("session".to_string()))
+        );
+    }
+
+    #[test]
+    fn same_site_none_requires_secure() {
+        let cookie = Cookie {
+            same_site: Some(SameSite::None),
+            ..Cookie::new("session", "a")
+        };
+
+        assert_eq!(
+            cookie.validate(),
+            Err(CookieError::InsecureSameSiteNone("session".to_string()))
+        );
+    }
+}
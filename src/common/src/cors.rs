@@ -0,0 +1,439 @@
+use crate::{
+    api_request::ApiRequest, api_response::ResponseBody, constants, error_code::CommonErrorCode,
+    ApiResponse,
+};
+use aws_lambda_events::http::{
+    header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+        ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+        ORIGIN, VARY,
+    },
+    HeaderMap, HeaderValue, Method,
+};
+
+// Meant to be declared once per service as a const, e.g.
+// const CORS_POLICY: CorsPolicy<'static> = CorsPolicy {
+//     allowed_origins: &["https://darkord.com"],
+//     stage_allowed_origins: &[("dev", &["http://localhost:3000"])],
+//     ..CorsPolicy::DEFAULT
+// };
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CorsPolicy<'a> {
+    // Either an exact origin, a subdomain wildcard like `https://*.darkord.com`, or `*` for any
+    // origin, which never gets credentials
+    pub allowed_origins: &'a [&'a str],
+
+    // Extra origins only allowed when the STAGE environment variable matches, e.g. localhost in dev
+    pub stage_allowed_origins: &'a [(&'a str, &'a [&'a str])],
+
+    pub allowed_methods: &'a [&'a str],
+
+    // `*` allows any header the browser asks for
+    pub allowed_headers: &'a [&'a str],
+
+    pub exposed_headers: &'a [&'a str],
+    pub allow_credentials: bool,
+    pub max_age: Option<u32>,
+}
+
+impl CorsPolicy<'_> {
+    pub const DEFAULT: Self = Self {
+        allowed_origins: &[],
+        stage_allowed_origins: &[],
+        allowed_methods: &["GET", "POST", "PUT", "PATCH", "DELETE"],
+        allowed_headers: &["Content-Type", "Authorization", "X-Api-Key"],
+        exposed_headers: &[],
+        allow_credentials: false,
+        max_age: None,
+    };
+
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.is_origin_matched(origin, true)
+    }
+
+    // `*` only matches when is_any_matched, so that an origin can be told apart from any origin
+    fn is_origin_matched(&self, origin: &str, is_any_matched: bool) -> bool {
+        let is_allowed = |allowed_origins: &[&str]| {
+            allowed_origins.iter().any(|&allowed_origin| {
+                (is_any_matched && allowed_origin == "*")
+                    || is_origin_matched(allowed_origin, origin)
+            })
+        };
+
+        if is_allowed(self.allowed_origins) {
+            return true;
+        }
+
+        if self.stage_allowed_origins.is_empty() {
+            return false;
+        }
+
+        constants::STAGE.with(|stage| {
+            self.stage_allowed_origins
+                .iter()
+                .filter(|&&(allowed_stage, _)| allowed_stage == stage)
+                .any(|&(_, allowed_origins)| is_allowed(allowed_origins))
+        })
+    }
+
+    // Answers an OPTIONS preflight request, or returns None when the event is not a preflight
+    pub fn preflight<'a>(
+        &self,
+        event: &impl ApiRequest,
+        request_id: &'a str,
+    ) -> Option<ApiResponse<'a>> {
+        let headers = event.headers();
+
+        if event.http_method() != Method::OPTIONS
+            || !headers.contains_key(ORIGIN)
+            || !headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            return None;
+        }
+
+        let api_resp = ApiResponse {
+            code: CommonErrorCode::NoContent.into(),
+            request_id,
+            body: ResponseBody::Empty,
+            ..Default::default()
+        };
+
+        // A disallowed preflight gets no CORS headers at all, so the browser blocks the request
+        let Some(allowed_origin) = self.allowed_origin(headers) else {
+            return Some(api_resp);
+        };
+
+        let is_method_allowed = headers
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| method.to_str().ok())
+            .is_some_and(|method| {
+                self.allowed_methods
+                    .iter()
+                    .any(|allowed_method| allowed_method.eq_ignore_ascii_case(method))
+            });
+
+        if !is_method_allowed {
+            return Some(api_resp);
+        }
+
+        let mut cors_headers = self.cors_headers(allowed_origin);
+
+        if let Ok(allowed_methods) = HeaderValue::from_str(&self.allowed_methods.join(", ")) {
+            cors_headers.insert(ACCESS_CONTROL_ALLOW_METHODS, allowed_methods);
+        }
+
+        let allowed_headers = if self.allowed_headers.contains(&"*") {
+            headers.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned()
+        } else {
+            HeaderValue::from_str(&self.allowed_headers.join(", ")).ok()
+        };
+
+        if let Some(allowed_headers) = allowed_headers {
+            cors_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+        }
+
+        if let Some(max_age) = self.max_age {
+            cors_headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.into());
+        }
+
+        Some(ApiResponse {
+            headers: cors_headers,
+            ..api_resp
+        })
+    }
+
+    pub(crate) fn apply(&self, event: &impl ApiRequest, headers: &mut HeaderMap) {
+        if let Some(cors_headers) = self.response_headers(event) {
+            merge_headers(headers, cors_headers);
+        }
+    }
+
+    // The CORS headers of a response to anything but a preflight request, or None when no origin is
+    // allowed at all, which leaves CORS to the handler
+    pub(crate) fn response_headers(&self, event: &impl ApiRequest) -> Option<HeaderMap> {
+        if self.allowed_origins.is_empty() && self.stage_allowed_origins.is_empty() {
+            return None;
+        }
+
+        let Some(allowed_origin) = self.allowed_origin(event.headers()) else {
+            // The response still depends on the origin as another origin may be allowed
+            let mut cors_headers = HeaderMap::new();
+            cors_headers.insert(VARY, HeaderValue::from_static("Origin"));
+            return Some(cors_headers);
+        };
+
+        let mut cors_headers = self.cors_headers(allowed_origin);
+
+        if !self.exposed_headers.is_empty() {
+            if let Ok(exposed_headers) = HeaderValue::from_str(&self.exposed_headers.join(", ")) {
+                cors_headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, exposed_headers);
+            }
+        }
+
+        Some(cors_headers)
+    }
+
+    fn allowed_origin<'b>(&self, headers: &'b HeaderMap) -> Option<AllowedOrigin<'b>> {
+        let origin = headers.get(ORIGIN)?;
+        let origin_str = origin.to_str().ok()?;
+
+        if self.is_origin_matched(origin_str, false) {
+            Some(AllowedOrigin::Origin(origin))
+        } else if self.is_origin_matched(origin_str, true) {
+            Some(AllowedOrigin::Any)
+        } else {
+            None
+        }
+    }
+
+    // A listed origin is echoed back so that credentials work with it, while an origin only allowed
+    // through `*` gets a literal `*`, as echoing any origin with credentials would let every site
+    // make credentialed requests. Either way the response depends on the origin, as a request
+    // without one or from another origin gets other headers
+    fn cors_headers(&self, allowed_origin: AllowedOrigin<'_>) -> HeaderMap {
+        let mut cors_headers = HeaderMap::new();
+        cors_headers.insert(VARY, HeaderValue::from_static("Origin"));
+
+        let AllowedOrigin::Origin(origin) = allowed_origin else {
+            cors_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+            return cors_headers;
+        };
+
+        cors_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+
+        if self.allow_credentials {
+            cors_headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+
+        cors_headers
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+enum AllowedOrigin<'a> {
+    Any,
+    Origin(&'a HeaderValue),
+}
+
+impl Default for CorsPolicy<'_> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// Adds to any Vary the response already has, e.g. Accept-Encoding, while every Access-Control-*
+// header from the handler is dropped, so that a disallowed origin never gets one
+pub(crate) fn merge_headers(headers: &mut HeaderMap, cors_headers: HeaderMap) {
+    let handler_cors_keys = headers
+        .keys()
+        .filter(|k| k.as_str().starts_with("access-control-"))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for k in handler_cors_keys {
+        headers.remove(k);
+    }
+
+    for (k, v) in &cors_headers {
+        headers.append(k, v.clone());
+    }
+}
+
+fn is_origin_matched(allowed_origin: &str, origin: &str) -> bool {
+    if allowed_origin.eq_ignore_ascii_case(origin) {
+        return true;
+    }
+
+    let Some((scheme, domain)) = allowed_origin.split_once("://*.") else {
+        return false;
+    };
+
+    let Some((origin_scheme, origin_host)) = origin.split_once("://") else {
+        return false;
+    };
+
+    let suffix = format!(".{domain}");
+
+    origin_scheme.eq_ignore_ascii_case(scheme)
+        && origin_host.len() > suffix.len()
+        && origin_host
+            .get(origin_host.len() - suffix.len()..)
+            .is_some_and(|origin_suffix| origin_suffix.eq_ignore_ascii_case(&suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_lambda_events::apigw::ApiGatewayProxyRequest;
+
+    const CORS_POLICY: CorsPolicy<'static> = CorsPolicy {
+        allowed_origins: &["https://darkord.com", "https://*.darkord.com"],
+        allow_credentials: true,
+        ..CorsPolicy::DEFAULT
+    };
+
+    fn event(origin: &str) -> ApiGatewayProxyRequest {
+        let mut event = ApiGatewayProxyRequest::default();
+        event
+            .headers
+            .insert(ORIGIN, HeaderValue::from_str(origin).unwrap());
+
+        event
+    }
+
+    #[test]
+    fn listed_origin_is_echoed_with_credentials() {
+        for origin in ["https://darkord.com", "https://api.darkord.com"] {
+            let headers = CORS_POLICY.response_headers(&event(origin)).unwrap();
+
+            assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+            assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+            assert_eq!(headers[VARY], "Origin");
+        }
+    }
+
+    #[test]
+    fn any_origin_gets_a_literal_star_without_credentials() {
+        let cors_policy = CorsPolicy {
+            allowed_origins: &["https://darkord.com", "*"],
+            ..CORS_POLICY
+        };
+
+        let headers = cors_policy
+            .response_headers(&event("https://evil.com"))
+            .unwrap();
+
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(headers[VARY], "Origin");
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+
+        let headers = cors_policy
+            .response_headers(&event("https://darkord.com"))
+            .unwrap();
+
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://darkord.com");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[VARY], "Origin");
+    }
+
+    #[test]
+    fn only_star_still_varies_by_origin() {
+        let cors_policy = CorsPolicy {
+            allowed_origins: &["*"],
+            ..CorsPolicy::DEFAULT
+        };
+
+        let headers = cors_policy
+            .response_headers(&event("https://evil.com"))
+            .unwrap();
+
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(headers[VARY], "Origin");
+
+        let headers = cors_policy
+            .response_headers(&ApiGatewayProxyRequest::default())
+            .unwrap();
+
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[VARY], "Origin");
+    }
+
+    #[test]
+    fn disallowed_origin_only_gets_vary() {
+        for origin in [
+            "https://evil.com",
+            "https://darkord.com.evil.com",
+            "http://darkord.com",
+        ] {
+            let headers = CORS_POLICY.response_headers(&event(origin)).unwrap();
+
+            assert_eq!(headers.len(), 1);
+            assert_eq!(headers[VARY], "Origin");
+        }
+    }
+
+    #[test]
+    fn apply_adds_to_the_existing_vary() {
+        let mut headers = HeaderMap::new();
+        headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("https://evil.com"),
+        );
+
+        CORS_POLICY.apply(&event("https://darkord.com"), &mut headers);
+
+        assert_eq!(
+            headers.get_all(VARY).iter().collect::<Vec<_>>(),
+            ["Accept-Encoding", "Origin"]
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://darkord.com");
+    }
+
+    #[test]
+    fn handler_cors_headers_are_dropped_for_disallowed_origin() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("https://evil.com"),
+        );
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+        headers.insert(
+            ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static("X-Secret"),
+        );
+        headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+
+        CORS_POLICY.apply(&event("https://evil.com"), &mut headers);
+
+        assert!(headers
+            .keys()
+            .all(|k| !k.as_str().starts_with("access-control-")));
+        assert_eq!(
+            headers.get_all(VARY).iter().collect::<Vec<_>>(),
+            ["Accept-Encoding", "Origin"]
+        );
+    }
+
+    #[test]
+    fn handler_cors_headers_are_kept_without_allowed_origins() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("https://darkord.com"),
+        );
+
+        CorsPolicy::DEFAULT.apply(&event("https://darkord.com"), &mut headers);
+
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://darkord.com");
+    }
+
+    #[test]
+    fn preflight_answers_allowed_methods() {
+        let mut event = event("https://darkord.com");
+        event.http_method = Method::OPTIONS;
+        event.headers.insert(
+            ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("PATCH"),
+        );
+
+        let api_resp = CORS_POLICY.preflight(&event, "request_id").unwrap();
+
+        assert_eq!(
+            api_resp.headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://darkord.com"
+        );
+        assert_eq!(
+            api_resp.headers[ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, POST, PUT, PATCH, DELETE"
+        );
+    }
+}
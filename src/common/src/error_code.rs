@@ -0,0 +1,386 @@
+use serde::{Serialize, Serializer};
+use std::fmt::{self, Display, Formatter};
+
+// Only made from the enums implementing ErrorCodes, whose definitions are validated at compile time
+// on conversion, so that every code sent to clients comes from a registry
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ErrorCode {
+    code: u32,
+    status_code: u16,
+    name: &'static str,
+    message: &'static str,
+}
+
+impl ErrorCode {
+    pub const fn code(&self) -> u32 {
+        self.code
+    }
+
+    pub const fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub const fn message(&self) -> &'static str {
+        self.message
+    }
+}
+
+// Implemented by the error_codes! macro
+pub trait ErrorCodes: Copy + 'static {
+    // Tells apart the codes of each service, where 0 is taken by CommonErrorCode
+    const SERVICE: u8;
+
+    // The code, HTTP status code, name and message of every variant, in declaration order
+    const DEFS: &'static [ErrorCodeDef];
+
+    fn index(self) -> usize;
+}
+
+pub type ErrorCodeDef = (u32, u16, &'static str, &'static str);
+
+// Not reachable from outside this crate, so that the check cannot be skipped by implementing
+// ErrorCodes by hand
+struct Check<T>(T);
+
+impl<T: ErrorCodes> Check<T> {
+    const IS_VALID: () = assert!(
+        are_defs_valid(T::SERVICE, T::DEFS),
+        "Error codes must be valid and must not collide with CommonErrorCode"
+    );
+}
+
+impl<T: ErrorCodes> From<T> for ErrorCode {
+    fn from(value: T) -> Self {
+        let () = Check::<T>::IS_VALID;
+
+        let (code, status_code, name, message) = T::DEFS[value.index()];
+
+        Self {
+            code,
+            status_code,
+            name,
+            message,
+        }
+    }
+}
+
+impl Default for ErrorCode {
+    fn default() -> Self {
+        CommonErrorCode::InternalServerError.into()
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{} ({})", self.name, self.code)
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.code)
+    }
+}
+
+// A code is made of a 3 digits HTTP status code, the 2 digits service number and 2 digits to tell
+// apart the codes of the service sharing the same HTTP status code, e.g. 4041201 for the second 404
+// of service 12
+#[doc(hidden)]
+pub const fn is_valid(code: u32, status_code: u16, service: u8) -> bool {
+    status_code >= 100
+        && status_code <= 599
+        && service <= 99
+        && code / 10_000 == status_code as u32
+        && code / 100 % 100 == service as u32
+}
+
+#[doc(hidden)]
+pub const fn is_valid_name(name: &str) -> bool {
+    let name = name.as_bytes();
+
+    if name.is_empty() {
+        return false;
+    }
+
+    let mut i = 0;
+
+    while i < name.len() {
+        if !matches!(name[i], b'A'..=b'Z' | b'0'..=b'9' | b'_') {
+            return false;
+        }
+
+        i += 1;
+    }
+
+    true
+}
+
+const fn are_defs_valid(service: u8, defs: &[ErrorCodeDef]) -> bool {
+    let mut i = 0;
+
+    while i < defs.len() {
+        if !is_valid(defs[i].0, defs[i].1, service) || !is_valid_name(defs[i].2) {
+            return false;
+        }
+
+        i += 1;
+    }
+
+    // Only CommonErrorCode itself may use service 0, while no other enum may reuse its names
+    are_defs_unique(&[defs])
+        && if service == 0 {
+            are_defs_eq(defs, CommonErrorCode::DEFS)
+        } else {
+            are_defs_unique(&[defs, CommonErrorCode::DEFS])
+        }
+}
+
+#[doc(hidden)]
+pub const fn are_defs_unique(defs: &[&[ErrorCodeDef]]) -> bool {
+    let mut i = 0;
+
+    while i < defs.len() {
+        let mut j = 0;
+
+        while j < defs[i].len() {
+            let mut k = i;
+            let mut l = j + 1;
+
+            while k < defs.len() {
+                while l < defs[k].len() {
+                    if defs[i][j].0 == defs[k][l].0 || is_str_eq(defs[i][j].2, defs[k][l].2) {
+                        return false;
+                    }
+
+                    l += 1;
+                }
+
+                k += 1;
+                l = 0;
+            }
+
+            j += 1;
+        }
+
+        i += 1;
+    }
+
+    true
+}
+
+const fn are_defs_eq(defs: &[ErrorCodeDef], other_defs: &[ErrorCodeDef]) -> bool {
+    if defs.len() != other_defs.len() {
+        return false;
+    }
+
+    let mut i = 0;
+
+    while i < defs.len() {
+        if defs[i].0 != other_defs[i].0 || !is_str_eq(defs[i].2, other_defs[i].2) {
+            return false;
+        }
+
+        i += 1;
+    }
+
+    true
+}
+
+const fn is_str_eq(a: &str, b: &str) -> bool {
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+
+        i += 1;
+    }
+
+    true
+}
+
+/// Declares the error codes of a service. Every code, HTTP status code and name is checked at compile
+/// time against the service number and CommonErrorCode, so a malformed or duplicated code fails the
+/// build instead of the Lambda. Service numbers are handed out once per service, so that the codes
+/// of different services never collide.
+///
+/// ```
+/// common::error_codes! {
+///     pub enum AuthErrorCode for service 12 {
+///         InvalidCredentials(4011200, 401, "INVALID_CREDENTIALS", "Invalid credentials"),
+///         AccountLocked(4031200, 403, "ACCOUNT_LOCKED", "Account is locked"),
+///     }
+/// }
+///
+/// common::error_codes! {
+///     pub enum OtpErrorCode for service 12 {
+///         WrongOtp(4011201, 401, "WRONG_OTP", "Wrong one-time password"),
+///     }
+/// }
+///
+/// common::assert_unique_error_codes!(AuthErrorCode, OtpErrorCode);
+/// ```
+///
+/// A code outside the service number fails the build:
+///
+/// ```compile_fail
+/// common::error_codes! {
+///     pub enum AuthErrorCode for service 12 {
+///         InvalidCredentials(4011300, 401, "INVALID_CREDENTIALS", "Invalid credentials"),
+///     }
+/// }
+/// ```
+///
+/// So does an enum implementing ErrorCodes by hand to reuse the codes of CommonErrorCode:
+///
+/// ```compile_fail
+/// use common::error_code::{ErrorCode, ErrorCodeDef, ErrorCodes};
+///
+/// #[derive(Clone, Copy)]
+/// struct FakeErrorCode;
+///
+/// impl ErrorCodes for FakeErrorCode {
+///     const SERVICE: u8 = 0;
+///     const DEFS: &'static [ErrorCodeDef] = &[(4000000, 400, "FAKE", "")];
+///
+///     fn index(self) -> usize {
+///         0
+///     }
+/// }
+///
+/// let _ = ErrorCode::from(FakeErrorCode);
+/// ```
+#[macro_export]
+macro_rules! error_codes {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident for service $service:literal {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident($code:literal, $status_code:literal, $err_name:literal, $message:literal)
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+        #[repr(u32)]
+        $vis enum $name {
+            $(
+                $(#[$variant_meta])*
+                $variant = $code,
+            )*
+        }
+
+        impl $name {
+            pub const ALL: &'static [Self] = &[$(Self::$variant),*];
+        }
+
+        impl $crate::error_code::ErrorCodes for $name {
+            const SERVICE: u8 = $service;
+
+            const DEFS: &'static [$crate::error_code::ErrorCodeDef] =
+                &[$(($code, $status_code, $err_name, $message)),*];
+
+            fn index(self) -> usize {
+                Self::DEFS
+                    .iter()
+                    .position(|def| def.0 == self as u32)
+                    .unwrap_or_default()
+            }
+        }
+
+        // Checked here too rather than only on conversion, to point at the offending code
+        const _: () = {
+            $(
+                assert!(
+                    $crate::error_code::is_valid($code, $status_code, $service),
+                    concat!(
+                        "Error code ",
+                        stringify!($code),
+                        " must be the HTTP status code ",
+                        stringify!($status_code),
+                        " followed by the service number ",
+                        stringify!($service),
+                        " in 2 digits and 2 more digits"
+                    )
+                );
+
+                assert!(
+                    $crate::error_code::is_valid_name($err_name),
+                    concat!("Error name ", $err_name, " must be in SCREAMING_SNAKE_CASE")
+                );
+            )*
+
+            assert!(
+                $crate::error_code::are_defs_unique(&[
+                    <$name as $crate::error_code::ErrorCodes>::DEFS
+                ]),
+                concat!("Error names of ", stringify!($name), " must be unique")
+            );
+        };
+
+        impl From<$name> for $crate::CommonError {
+            fn from(value: $name) -> Self {
+                $crate::CommonError {
+                    code: value.into(),
+                    message: "".to_string(),
+                }
+            }
+        }
+    };
+}
+
+/// Fails the build when any 2 of the given error code enums of the same service share a code or a
+/// name.
+#[macro_export]
+macro_rules! assert_unique_error_codes {
+    ($($name:path),+ $(,)?) => {
+        const _: () = assert!(
+            $crate::error_code::are_defs_unique(&[
+                $(<$name as $crate::error_code::ErrorCodes>::DEFS),+
+            ]),
+            concat!("Error codes and names must be unique across ", stringify!($($name),+))
+        );
+    };
+}
+
+crate::error_codes! {
+    pub enum CommonErrorCode for service 0 {
+        Ok(2000000, 200, "OK", ""),
+        NoContent(2040000, 204, "NO_CONTENT", ""),
+        MovedPermanently(3010000, 301, "MOVED_PERMANENTLY", ""),
+        Found(3020000, 302, "FOUND", ""),
+        SeeOther(3030000, 303, "SEE_OTHER", ""),
+        TemporaryRedirect(3070000, 307, "TEMPORARY_REDIRECT", ""),
+        PermanentRedirect(3080000, 308, "PERMANENT_REDIRECT", ""),
+        BadRequest(4000000, 400, "BAD_REQUEST", "Bad request"),
+        InvalidBody(4000001, 400, "INVALID_BODY", "Invalid request body"),
+        InvalidRequest(4000002, 400, "INVALID_REQUEST", "Invalid request"),
+        ValidationFailed(4000003, 400, "VALIDATION_FAILED", "Validation failed"),
+        ConflictingField(4000004, 400, "CONFLICTING_FIELD", "Field comes from more than 1 source"),
+        FieldSourceNotAllowed(
+            4000005,
+            400,
+            "FIELD_SOURCE_NOT_ALLOWED",
+            "Field source is not allowed"
+        ),
+        Unauthorized(4010000, 401, "UNAUTHORIZED", "Unauthorized"),
+        Forbidden(4030000, 403, "FORBIDDEN", "Forbidden"),
+        NotFound(4040000, 404, "NOT_FOUND", "Data was not found"),
+        Conflict(4090000, 409, "CONFLICT", "Conflict"),
+        UnsupportedMediaType(4150000, 415, "UNSUPPORTED_MEDIA_TYPE", "Unsupported media type"),
+        InternalServerError(5000000, 500, "INTERNAL_SERVER_ERROR", "Internal server error"),
+    }
+}
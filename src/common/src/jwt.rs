@@ -0,0 +1,590 @@
+use crate::{common_enums::JwtAlgorithm, error_code::CommonErrorCode, CommonError};
+use jsonwebtoken::{
+    errors::Error as JsonWebTokenError,
+    jwk::{AlgorithmParameters, Jwk, JwkSet, KeyAlgorithm, PublicKeyUse},
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs, io,
+    path::Path,
+};
+
+// The registered claims checked by JwtVerifier, with the service specific claims flattened next to
+// them, e.g. Claims<UserClaims> where UserClaims { role: String }
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims<T> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+
+    // Serialized as a single string when there is only 1 audience
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "audience")]
+    pub aud: Vec<String>,
+
+    pub exp: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+
+    #[serde(flatten)]
+    pub custom: T,
+}
+
+impl<T> Claims<T> {
+    // Issued now, expiring after ttl seconds, with a random jti
+    pub fn new(custom: T, ttl: u64) -> Result<Self, JwtError> {
+        let now = now()?;
+
+        Ok(Self {
+            iss: None,
+            sub: None,
+            aud: vec![],
+            exp: now.saturating_add(ttl),
+            nbf: None,
+            iat: Some(now),
+            jti: Some(crate::gen_secret_token().byte_count(16usize).call()),
+            custom,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct JwtSigner {
+    algorithm: JwtAlgorithm,
+    key: EncodingKey,
+    kid: Option<String>,
+}
+
+impl JwtSigner {
+    // The key is the secret for HS256, or else the PEM encoded private key
+    pub fn new(algorithm: JwtAlgorithm, key: &[u8]) -> Result<Self, JwtError> {
+        let key = match algorithm {
+            JwtAlgorithm::Hs256 => EncodingKey::from_secret(key),
+            JwtAlgorithm::Rs256 => EncodingKey::from_rsa_pem(key).map_err(JwtError::Key)?,
+            JwtAlgorithm::Es256 => EncodingKey::from_ec_pem(key).map_err(JwtError::Key)?,
+            JwtAlgorithm::EdDsa => EncodingKey::from_ed_pem(key).map_err(JwtError::Key)?,
+        };
+
+        Ok(Self {
+            algorithm,
+            key,
+            kid: None,
+        })
+    }
+
+    // Lets verifiers holding many keys, e.g. from a JWKS, pick the right one
+    pub fn kid(mut self, kid: &str) -> Self {
+        self.kid = Some(kid.to_string());
+        self
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &Claims<T>) -> Result<String, JwtError> {
+        let header = Header {
+            kid: self.kid.clone(),
+            ..Header::new(to_algorithm(self.algorithm))
+        };
+
+        jsonwebtoken::encode(&header, claims, &self.key).map_err(JwtError::Key)
+    }
+}
+
+#[derive(Clone)]
+struct VerifyingKey {
+    kid: Option<String>,
+    algorithm: JwtAlgorithm,
+    key: DecodingKey,
+}
+
+// Verifies the signature with the key matching the token header, then checks exp, nbf and iat
+// against the current time give or take the leeway, as well as iss, aud and jti when configured
+#[derive(Clone, Default)]
+pub struct JwtVerifier {
+    keys: Vec<VerifyingKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: u64,
+    is_jti_required: bool,
+}
+
+impl JwtVerifier {
+    // The key is the secret for HS256, or else the PEM encoded public key
+    pub fn new(algorithm: JwtAlgorithm, key: &[u8]) -> Result<Self, JwtError> {
+        Self::default().key(None, algorithm, key)
+    }
+
+    // Keys of IdPs usually come with keys this verifier cannot use, e.g. RS384, PS256 or encryption
+    // keys, which are skipped, so this only fails when no key is left
+    pub fn from_jwks(jwks: &JwkSet) -> Result<Self, JwtError> {
+        let keys = jwks
+            .keys
+            .iter()
+            .filter(|jwk| {
+                jwk.common
+                    .public_key_use
+                    .as_ref()
+                    .map_or(true, |key_use| *key_use == PublicKeyUse::Signature)
+            })
+            .filter_map(|jwk| {
+                Some(VerifyingKey {
+                    kid: jwk.common.key_id.clone(),
+                    algorithm: jwk_algorithm(jwk).ok()?,
+                    key: DecodingKey::from_jwk(jwk).ok()?,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if keys.is_empty() {
+            return Err(JwtError::NoUsableKey);
+        }
+
+        Ok(Self {
+            keys,
+            ..Default::default()
+        })
+    }
+
+    // Keys of unknown key types are skipped like the other keys which cannot be used, rather than
+    // failing to parse the whole JWKS
+    pub fn from_jwks_json(jwks: &str) -> Result<Self, JwtError> {
+        #[derive(Deserialize)]
+        struct RawJwkSet {
+            keys: Vec<serde_json::Value>,
+        }
+
+        let jwks = serde_json::from_str::<RawJwkSet>(jwks).map_err(JwtError::Jwks)?;
+
+        Self::from_jwks(&JwkSet {
+            keys: jwks
+                .keys
+                .into_iter()
+                .filter_map(|jwk| serde_json::from_value(jwk).ok())
+                .collect(),
+        })
+    }
+
+    pub fn from_jwks_file(path: impl AsRef<Path>) -> Result<Self, JwtError> {
+        Self::from_jwks_json(&fs::read_to_string(path).map_err(JwtError::Io)?)
+    }
+
+    // Adds another key, e.g. the next key during a key rotation
+    pub fn key(
+        mut self,
+        kid: Option<&str>,
+        algorithm: JwtAlgorithm,
+        key: &[u8],
+    ) -> Result<Self, JwtError> {
+        let key = match algorithm {
+            JwtAlgorithm::Hs256 => DecodingKey::from_secret(key),
+            JwtAlgorithm::Rs256 => DecodingKey::from_rsa_pem(key).map_err(JwtError::Key)?,
+            JwtAlgorithm::Es256 => DecodingKey::from_ec_pem(key).map_err(JwtError::Key)?,
+            JwtAlgorithm::EdDsa => DecodingKey::from_ed_pem(key).map_err(JwtError::Key)?,
+        };
+
+        self.keys.push(VerifyingKey {
+            kid: kid.map(str::to_string),
+            algorithm,
+            key,
+        });
+
+        Ok(self)
+    }
+
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    // Seconds of clock skew tolerated between the issuer and this service
+    pub fn leeway(mut self, leeway: u64) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    pub fn require_jti(mut self) -> Self {
+        self.is_jti_required = true;
+        self
+    }
+
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<Claims<T>, JwtError> {
+        let header = jsonwebtoken::decode_header(token).map_err(JwtError::Token)?;
+        let algorithm = from_algorithm(header.alg)?;
+
+        // A key with the same kid is picked first, or else a key for the algorithm when either side
+        // has no kid. The picked key must still be for the token algorithm, so that a token cannot
+        // e.g. have an RS256 public key used as an HS256 secret
+        let key = self
+            .keys
+            .iter()
+            .find(|key| header.kid.is_some() && key.kid == header.kid)
+            .or_else(|| {
+                self.keys.iter().find(|key| {
+                    key.algorithm == algorithm && (header.kid.is_none() || key.kid.is_none())
+                })
+            })
+            .filter(|key| key.algorithm == algorithm)
+            .ok_or_else(|| JwtError::UnknownKey(header.kid.clone()))?;
+
+        // Only the signature is verified here, the claims are checked below against our own clock
+        let mut validation = Validation::new(header.alg);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        validation.validate_nbf = false;
+        validation.validate_aud = false;
+
+        let claims = jsonwebtoken::decode::<Claims<T>>(token, &key.key, &validation)
+            .map_err(JwtError::Token)?
+            .claims;
+
+        let now = now()?;
+
+        // Saturating as the claims come from the token, where exp may be as large as u64::MAX
+        if claims.exp.saturating_add(self.leeway) <= now {
+            return Err(JwtError::Expired);
+        }
+
+        if claims
+            .nbf
+            .is_some_and(|nbf| nbf > now.saturating_add(self.leeway))
+        {
+            return Err(JwtError::NotYetValid);
+        }
+
+        if claims
+            .iat
+            .is_some_and(|iat| iat > now.saturating_add(self.leeway))
+        {
+            return Err(JwtError::IssuedInFuture);
+        }
+
+        if self.issuer.is_some() && claims.iss != self.issuer {
+            return Err(JwtError::Issuer);
+        }
+
+        if let Some(audience) = &self.audience {
+            if !claims.aud.contains(audience) {
+                return Err(JwtError::Audience);
+            }
+        }
+
+        if self.is_jti_required && claims.jti.as_deref().map_or(true, str::is_empty) {
+            return Err(JwtError::MissingJti);
+        }
+
+        Ok(claims)
+    }
+}
+
+#[derive(Debug)]
+pub enum JwtError {
+    Key(JsonWebTokenError),
+    Jwks(serde_json::Error),
+    NoUsableKey,
+    Io(io::Error),
+    Clock(anyhow::Error),
+    Algorithm(String),
+    UnknownKey(Option<String>),
+    Token(JsonWebTokenError),
+    Expired,
+    NotYetValid,
+    IssuedInFuture,
+    Issuer,
+    Audience,
+    MissingJti,
+}
+
+impl Display for JwtError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(err) => write!(fmt, "Invalid JWT key: {err}"),
+            Self::Jwks(err) => write!(fmt, "Invalid JWKS: {err}"),
+            Self::NoUsableKey => write!(fmt, "JWKS has no key which can be used"),
+            Self::Io(err) => write!(fmt, "Failed to read the JWKS: {err}"),
+            Self::Clock(err) => write!(fmt, "Failed to get the current time: {err}"),
+            Self::Algorithm(alg) => write!(fmt, "Unsupported JWT algorithm: {alg}"),
+            Self::UnknownKey(Some(kid)) => write!(fmt, "No JWT key found for kid: {kid}"),
+            Self::UnknownKey(None) => write!(fmt, "No JWT key found"),
+            Self::Token(err) => write!(fmt, "Invalid JWT: {err}"),
+            Self::Expired => write!(fmt, "JWT has expired"),
+            Self::NotYetValid => write!(fmt, "JWT is not valid yet"),
+            Self::IssuedInFuture => write!(fmt, "JWT is issued in the future"),
+            Self::Issuer => write!(fmt, "JWT has an unexpected issuer"),
+            Self::Audience => write!(fmt, "JWT has an unexpected audience"),
+            Self::MissingJti => write!(fmt, "JWT is missing the jti"),
+        }
+    }
+}
+
+impl Error for JwtError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Key(err) | Self::Token(err) => Some(err),
+            Self::Jwks(err) => Some(err),
+            Self::Io(err) => Some(err),
+            Self::Clock(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// Bad tokens are the client's fault, while bad keys and JWKS are a misconfiguration of the service
+impl From<JwtError> for CommonError {
+    fn from(err: JwtError) -> Self {
+        match err {
+            JwtError::Key(_)
+            | JwtError::Jwks(_)
+            | JwtError::NoUsableKey
+            | JwtError::Io(_)
+            | JwtError::Clock(_) => CommonErrorCode::InternalServerError.into(),
+            _ => CommonError {
+                code: CommonErrorCode::Unauthorized.into(),
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+fn now() -> Result<u64, JwtError> {
+    crate::get_current_timestamp()
+        .call()
+        .map_err(JwtError::Clock)
+}
+
+const fn to_algorithm(algorithm: JwtAlgorithm) -> Algorithm {
+    match algorithm {
+        JwtAlgorithm::Hs256 => Algorithm::HS256,
+        JwtAlgorithm::Rs256 => Algorithm::RS256,
+        JwtAlgorithm::Es256 => Algorithm::ES256,
+        JwtAlgorithm::EdDsa => Algorithm::EdDSA,
+    }
+}
+
+fn from_algorithm(algorithm: Algorithm) -> Result<JwtAlgorithm, JwtError> {
+    match algorithm {
+        Algorithm::HS256 => Ok(JwtAlgorithm::Hs256),
+        Algorithm::RS256 => Ok(JwtAlgorithm::Rs256),
+        Algorithm::ES256 => Ok(JwtAlgorithm::Es256),
+        Algorithm::EdDSA => Ok(JwtAlgorithm::EdDsa),
+        algorithm => Err(JwtError::Algorithm(format!("{algorithm:?}"))),
+    }
+}
+
+// Keys without an alg are assumed to use the only supported algorithm of their key type
+fn jwk_algorithm(jwk: &Jwk) -> Result<JwtAlgorithm, JwtError> {
+    match (jwk.common.key_algorithm, &jwk.algorithm) {
+        (Some(KeyAlgorithm::HS256), _) | (None, AlgorithmParameters::OctetKey(_)) => {
+            Ok(JwtAlgorithm::Hs256)
+        }
+        (Some(KeyAlgorithm::RS256), _) | (None, AlgorithmParameters::RSA(_)) => {
+            Ok(JwtAlgorithm::Rs256)
+        }
+        (Some(KeyAlgorithm::ES256), _) | (None, AlgorithmParameters::EllipticCurve(_)) => {
+            Ok(JwtAlgorithm::Es256)
+        }
+        (Some(KeyAlgorithm::EdDSA), _) | (None, AlgorithmParameters::OctetKeyPair(_)) => {
+            Ok(JwtAlgorithm::EdDsa)
+        }
+        (Some(algorithm), _) => Err(JwtError::Algorithm(format!("{algorithm:?}"))),
+    }
+}
+
+mod audience {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Audience {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    pub(super) fn serialize<S: Serializer>(
+        aud: &[String],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match aud {
+            [aud] => aud.serialize(serializer),
+            aud => aud.serialize(serializer),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        match Audience::deserialize(deserializer)? {
+            Audience::One(aud) => Ok(vec![aud]),
+            Audience::Many(aud) => Ok(aud),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HS256_SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct UserClaims {
+        role: String,
+    }
+
+    fn sign(signer: &JwtSigner, exp: u64) -> String {
+        signer
+            .sign(&Claims {
+                exp,
+                ..Claims::new((), 60).unwrap()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn token_signed_with_each_algorithm_is_verified() {
+        for (algorithm, private_key, public_key) in [
+            (JwtAlgorithm::Hs256, HS256_SECRET, HS256_SECRET),
+            (
+                JwtAlgorithm::Rs256,
+                &include_bytes!("../testdata/jwt/rs256.pem")[..],
+                &include_bytes!("../testdata/jwt/rs256_pub.pem")[..],
+            ),
+            (
+                JwtAlgorithm::Es256,
+                &include_bytes!("../testdata/jwt/es256.pem")[..],
+                &include_bytes!("../testdata/jwt/es256_pub.pem")[..],
+            ),
+            (
+                JwtAlgorithm::EdDsa,
+                &include_bytes!("../testdata/jwt/eddsa.pem")[..],
+                &include_bytes!("../testdata/jwt/eddsa_pub.pem")[..],
+            ),
+        ] {
+            let signer = JwtSigner::new(algorithm, private_key).unwrap();
+            let claims = Claims {
+                iss: Some("darkord".to_string()),
+                aud: vec!["api".to_string()],
+                ..Claims::new(
+                    UserClaims {
+                        role: "admin".to_string(),
+                    },
+                    60,
+                )
+                .unwrap()
+            };
+            let token = signer.sign(&claims).unwrap();
+
+            let verifier = JwtVerifier::new(algorithm, public_key)
+                .unwrap()
+                .issuer("darkord")
+                .audience("api");
+
+            assert_eq!(verifier.verify::<UserClaims>(&token).unwrap(), claims);
+        }
+    }
+
+    #[test]
+    fn key_with_the_same_kid_is_picked_first() {
+        let signer = JwtSigner::new(JwtAlgorithm::Hs256, b"next secret")
+            .unwrap()
+            .kid("next");
+
+        let verifier = JwtVerifier::new(JwtAlgorithm::Hs256, HS256_SECRET)
+            .unwrap()
+            .key(Some("next"), JwtAlgorithm::Hs256, b"next secret")
+            .unwrap();
+
+        assert!(verifier.verify::<()>(&sign(&signer, u64::MAX)).is_ok());
+    }
+
+    #[test]
+    fn key_for_another_algorithm_is_not_used() {
+        let signer = JwtSigner::new(JwtAlgorithm::Hs256, HS256_SECRET)
+            .unwrap()
+            .kid("rsa");
+
+        let verifier = JwtVerifier::default()
+            .key(
+                Some("rsa"),
+                JwtAlgorithm::Rs256,
+                include_bytes!("../testdata/jwt/rs256_pub.pem"),
+            )
+            .unwrap();
+
+        assert!(matches!(
+            verifier.verify::<()>(&sign(&signer, u64::MAX)),
+            Err(JwtError::UnknownKey(Some(kid))) if kid == "rsa"
+        ));
+    }
+
+    #[test]
+    fn unusable_jwks_keys_are_skipped() {
+        let verifier =
+            JwtVerifier::from_jwks_json(include_str!("../testdata/jwt/jwks_mixed.json")).unwrap();
+
+        assert_eq!(
+            verifier
+                .keys
+                .iter()
+                .map(|key| (key.kid.as_deref(), key.algorithm))
+                .collect::<Vec<_>>(),
+            [(Some("rs256"), JwtAlgorithm::Rs256)]
+        );
+
+        let signer = JwtSigner::new(
+            JwtAlgorithm::Rs256,
+            include_bytes!("../testdata/jwt/rs256.pem"),
+        )
+        .unwrap()
+        .kid("rs256");
+
+        assert!(verifier.verify::<()>(&sign(&signer, u64::MAX)).is_ok());
+    }
+
+    #[test]
+    fn jwks_without_usable_keys_is_rejected() {
+        let jwks = r#"{
+            "keys": [
+                { "kty": "PQC", "kid": "unknown-kty" },
+                { "kty": "oct", "kid": "hs512", "alg": "HS512", "k": "c2VjcmV0" },
+                { "kty": "oct", "kid": "enc", "use": "enc", "k": "c2VjcmV0" }
+            ]
+        }"#;
+
+        assert!(matches!(
+            JwtVerifier::from_jwks_json(jwks),
+            Err(JwtError::NoUsableKey)
+        ));
+        assert!(matches!(
+            JwtVerifier::from_jwks_json("[]"),
+            Err(JwtError::Jwks(_))
+        ));
+    }
+
+    #[test]
+    fn exp_is_checked_without_overflowing() {
+        let signer = JwtSigner::new(JwtAlgorithm::Hs256, HS256_SECRET).unwrap();
+        let verifier = JwtVerifier::new(JwtAlgorithm::Hs256, HS256_SECRET)
+            .unwrap()
+            .leeway(60);
+
+        assert!(verifier.verify::<()>(&sign(&signer, u64::MAX)).is_ok());
+        assert!(matches!(
+            verifier.verify::<()>(&sign(&signer, 1)),
+            Err(JwtError::Expired)
+        ));
+    }
+}
@@ -1,37 +1,79 @@
 #![deny(elided_lifetimes_in_paths)]
 
+// Lets the derive macros, which refer to ::common, be used within this crate too
+extern crate self as common;
+
+pub mod api_handler;
+pub mod api_request;
 pub mod api_response;
+pub mod auth_policy;
 pub mod common_enums;
 pub mod common_error;
 pub mod common_serde;
 pub mod common_tracing;
 pub mod constants;
+pub mod cookie;
+pub mod cors;
+pub mod error_code;
+#[cfg(feature = "jwt")]
+pub mod jwt;
 pub mod method_arn;
+pub mod middleware;
+pub mod otp;
+pub mod password;
+pub mod redacted;
+pub mod redaction;
+pub mod refresh_token;
+mod request_body;
+mod request_de;
+pub mod secret;
+pub mod sensitive;
 pub mod sensitive_data;
 pub mod trimmed_string;
+pub mod uploaded_file;
+pub mod verification_code;
 
+pub use api_handler::{run_api_handler, run_api_service};
+pub use api_request::ApiRequest;
 pub use api_response::ApiResponse;
+pub use auth_policy::AuthPolicy;
+pub use common_derive::{Request, Sensitive};
 pub use common_error::CommonError;
-pub use method_arn::MethodArn;
+pub use cookie::{Cookie, CookieError, CookieJar};
+pub use cors::CorsPolicy;
+pub use error_code::ErrorCode;
+#[cfg(feature = "jwt")]
+pub use jwt::{Claims, JwtError, JwtSigner, JwtVerifier};
+pub use method_arn::{MethodArn, MethodArnError};
+pub use otp::{Hotp, Totp};
+pub use password::{PasswordError, PasswordHasher, PasswordParams};
+pub use redacted::{Redacted, RedactedNewBuilder};
+pub use redaction::RedactionRules;
+pub use refresh_token::{
+    InMemoryRevocationStore, RefreshTokenError, RefreshTokenRecord, RefreshTokenRotator,
+    RevocationStore,
+};
+pub use secret::Secret;
+pub use sensitive::Sensitive;
 pub use sensitive_data::SensitiveData;
 pub use sensitive_data::SensitiveDataNewBuilder;
 pub use trimmed_string::TrimmedString;
+pub use uploaded_file::UploadedFile;
+pub use verification_code::{
+    VerificationCodeError, VerificationCodePolicy, VerificationCodeRecord,
+};
 
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use lambda_runtime::Context;
 use optarg2chain::optarg_fn;
-use scrypt::{
-    password_hash::{
-        rand_core::{OsRng, RngCore},
-        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
-    },
-    Params, Scrypt,
-};
+use scrypt::password_hash::rand_core::{OsRng, RngCore};
 use std::{
     hash::{DefaultHasher, Hash, Hasher},
     panic::Location,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tokio::{task, time};
 
 pub trait Case {
     fn convert_snake_case_to_pascal_case(&self) -> String;
@@ -171,35 +213,123 @@ pub fn extend_current_timestamp(
     Ok(result)
 }
 
-pub fn hash_secret(secret: &str) -> String {
-    Scrypt
-        .hash_password_customized(
-            secret.as_bytes(),
-            None,
-            None,
-            Params::new(15, 8, 1, 32).unwrap(),
-            &SaltString::generate(&mut OsRng),
-        )
-        .unwrap()
-        .to_string()
+pub fn hash_secret(secret: &str) -> Result<String, PasswordError> {
+    PasswordHasher::default().hash(secret)
 }
 
+// False for a malformed hash too, use PasswordHasher::verify to tell the failures apart
 pub fn verify_secret(secret: &str, hashed_secret: &str) -> bool {
-    Scrypt
-        .verify_password(
-            secret.as_bytes(),
-            &PasswordHash::new(hashed_secret).unwrap(),
-        )
+    PasswordHasher::default()
+        .verify(secret, hashed_secret)
         .is_ok()
 }
 
+// Same as hash_secret, but runs on the blocking pool so that the runtime keeps serving other tasks,
+// and gives up when the Lambda function is about to time out
+pub async fn hash_secret_async(secret: &str, context: &Context) -> Result<String> {
+    let secret = secret.to_string();
+
+    spawn_blocking_before_timeout(context, move || hash_secret(&secret))
+        .await
+        .context(Location::caller())?
+        .context(Location::caller())
+}
+
+pub async fn verify_secret_async(
+    secret: &str,
+    hashed_secret: &str,
+    context: &Context,
+) -> Result<bool> {
+    let secret = secret.to_string();
+    let hashed_secret = hashed_secret.to_string();
+
+    spawn_blocking_before_timeout(context, move || verify_secret(&secret, &hashed_secret))
+        .await
+        .context(Location::caller())
+}
+
+// The blocking task cannot be cancelled, so it keeps running after a timeout but its result is
+// thrown away
+async fn spawn_blocking_before_timeout<T: Send + 'static>(
+    context: &Context,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T> {
+    if is_almost_timeout(context)
+        .call()
+        .context(Location::caller())?
+    {
+        bail!("Lambda function is almost timing out");
+    }
+
+    let now = get_current_timestamp()
+        .unit(Unit::Milliseconds)
+        .call()
+        .context(Location::caller())?;
+
+    let almost_deadline = extend_current_timestamp()
+        .src_timestamp(context.deadline)
+        .unit(Unit::Milliseconds)
+        .seconds(-1)
+        .call()
+        .context(Location::caller())?;
+
+    let timeout = Duration::from_millis(almost_deadline.saturating_sub(now));
+
+    time::timeout(timeout, task::spawn_blocking(f))
+        .await
+        .context(Location::caller())?
+        .context(Location::caller())
+}
+
 #[optarg_fn(GenSecretDigitsBuilder, call)]
 pub fn gen_secret_digits(#[optarg(6)] digit_count: u32) -> String {
-    format!(
-        "{:0width$}",
-        OsRng.next_u32() % 10i32.pow(digit_count) as u32,
-        width = digit_count as usize
-    )
+    pick_secret_chars(digit_count as _, b"0123456789")
+}
+
+// Each char is picked uniformly from the alphabet, e.g. b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789", which
+// must have between 1 and 256 chars
+pub fn gen_secret_chars(char_count: usize, alphabet: &[u8]) -> Result<String> {
+    if alphabet.is_empty() || alphabet.len() > 256 {
+        bail!(
+            "Alphabet must have between 1 and 256 chars but has {}",
+            alphabet.len()
+        );
+    }
+
+    Ok(pick_secret_chars(char_count, alphabet))
+}
+
+// Same as gen_secret_chars, for alphabets known to be valid
+pub(crate) fn pick_secret_chars(char_count: usize, alphabet: &[u8]) -> String {
+    // Random bytes at or above the largest multiple of the alphabet length are thrown away, as
+    // taking them modulo the alphabet length would favor the first chars of the alphabet
+    let limit = 256 - 256 % alphabet.len();
+    let mut chars = String::with_capacity(char_count);
+    let mut remaining_char_count = char_count;
+    let mut bytes = [0; 64];
+
+    while remaining_char_count > 0 {
+        OsRng.fill_bytes(&mut bytes);
+
+        for &byte in bytes
+            .iter()
+            .filter(|&&byte| usize::from(byte) < limit)
+            .take(remaining_char_count)
+        {
+            chars.push(char::from(alphabet[usize::from(byte) % alphabet.len()]));
+            remaining_char_count -= 1;
+        }
+    }
+
+    chars
+}
+
+// URL safe and unpadded, so it can be used as is in cookies, headers and query strings
+#[optarg_fn(GenSecretTokenBuilder, call)]
+pub fn gen_secret_token(#[optarg(32)] byte_count: usize) -> String {
+    let mut bytes = vec![0; byte_count];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
 }
 
 pub fn hash(value: &impl Hash) -> u64 {
@@ -227,3 +357,89 @@ pub fn is_almost_timeout<'a>(
 
     Ok(now >= almost_deadline)
 }
+
+// `*` matches any sequence of characters including `/`, and `?` matches any single character
+pub(crate) fn is_glob_matched(pattern: &[u8], value: &[u8]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_v)) => {
+                    p = star_p + 1;
+                    v = star_v + 1;
+                    backtrack = Some((star_p, star_v + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use tokio::runtime;
+
+    fn context(deadline_ms_from_now: i64) -> Context {
+        let mut context = Context::default();
+
+        context.deadline = extend_current_timestamp()
+            .unit(Unit::Milliseconds)
+            .milliseconds(deadline_ms_from_now)
+            .call()
+            .unwrap();
+
+        context
+    }
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn secret_hashed_async_is_verified_async() {
+        let context = context(60_000);
+
+        block_on(async {
+            let hash = hash_secret_async("secret", &context).await.unwrap();
+
+            assert!(verify_secret_async("secret", &hash, &context)
+                .await
+                .unwrap());
+            assert!(!verify_secret_async("Secret", &hash, &context)
+                .await
+                .unwrap());
+        });
+    }
+
+    #[test]
+    fn secret_is_not_hashed_near_the_deadline() {
+        for deadline_ms_from_now in [500, -500] {
+            let context = context(deadline_ms_from_now);
+
+            block_on(async {
+                assert!(hash_secret_async("secret", &context).await.is_err());
+                assert!(verify_secret_async("secret", "hash", &context)
+                    .await
+                    .is_err());
+            });
+        }
+    }
+}
@@ -1,20 +1,261 @@
-#[derive(Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+use crate::common_enums::Method;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+// The ARN of the API method being called, as given to Lambda authorizers, e.g.
+// arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET/users/123
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MethodArn {
-    pub api_arn: String,
+    pub partition: String,
+    pub region: String,
+    pub account_id: String,
+    pub api_id: String,
     pub stage: String,
-    pub method: String,
-    pub path: String,
+    pub method: Method,
+
+    // None when the ARN ends at the method, e.g. abcdef1234/dev/GET, and empty for the root path,
+    // e.g. abcdef1234/dev/GET/
+    pub path: Option<Vec<String>>,
+}
+
+impl MethodArn {
+    // Whether this ARN, which may contain `*` and `?` wildcards like an IAM policy resource, covers
+    // the other ARN
+    pub fn matches(&self, other: &Self) -> bool {
+        crate::is_glob_matched(self.to_string().as_bytes(), other.to_string().as_bytes())
+    }
+}
+
+impl FromStr for MethodArn {
+    type Err = MethodArnError;
+
+    fn from_str(method_arn: &str) -> Result<Self, Self::Err> {
+        let mut parts = method_arn.splitn(6, ':');
+
+        if parts.next() != Some("arn") {
+            return Err(MethodArnError::Prefix);
+        }
+
+        let partition = parts.next().ok_or(MethodArnError::Missing("partition"))?;
+        let service = parts.next().ok_or(MethodArnError::Missing("service"))?;
+
+        if service != "execute-api" {
+            return Err(MethodArnError::Service(service.to_string()));
+        }
+
+        let region = parts.next().ok_or(MethodArnError::Missing("region"))?;
+        let account_id = parts.next().ok_or(MethodArnError::Missing("account id"))?;
+        let resource = parts.next().ok_or(MethodArnError::Missing("API id"))?;
+
+        let mut resource = resource.splitn(4, '/');
+        let api_id = resource.next().unwrap_or_default();
+        let stage = resource.next().ok_or(MethodArnError::Missing("stage"))?;
+        let method = resource.next().ok_or(MethodArnError::Missing("method"))?;
+        let method =
+            Method::from_name(method).ok_or_else(|| MethodArnError::Method(method.to_string()))?;
+
+        let path = resource.next().map(|path| {
+            if path.is_empty() {
+                vec![]
+            } else {
+                path.split('/').map(str::to_string).collect()
+            }
+        });
+
+        for (name, value) in [
+            ("partition", partition),
+            ("region", region),
+            ("account id", account_id),
+            ("API id", api_id),
+            ("stage", stage),
+        ] {
+            if value.is_empty() {
+                return Err(MethodArnError::Missing(name));
+            }
+        }
+
+        Ok(Self {
+            partition: partition.to_string(),
+            region: region.to_string(),
+            account_id: account_id.to_string(),
+            api_id: api_id.to_string(),
+            stage: stage.to_string(),
+            method,
+            path,
+        })
+    }
 }
 
-impl From<&str> for MethodArn {
-    fn from(method_arn: &str) -> Self {
-        let mut method_arn = method_arn.split('/');
+impl TryFrom<&str> for MethodArn {
+    type Error = MethodArnError;
+
+    fn try_from(method_arn: &str) -> Result<Self, Self::Error> {
+        method_arn.parse()
+    }
+}
 
-        Self {
-            api_arn: method_arn.next().unwrap().to_string(),
-            stage: method_arn.next().unwrap().to_string(),
-            method: method_arn.next().unwrap().to_string(),
-            path: method_arn.collect::<Vec<_>>().join("/"),
+impl Display for MethodArn {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "arn:{}:execute-api:{}:{}:{}/{}/{}",
+            self.partition,
+            self.region,
+            self.account_id,
+            self.api_id,
+            self.stage,
+            self.method.as_str(),
+        )?;
+
+        if let Some(path) = &self.path {
+            write!(fmt, "/{}", path.join("/"))?;
         }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MethodArnError {
+    Prefix,
+    Service(String),
+    Missing(&'static str),
+    Method(String),
+}
+
+impl Display for MethodArnError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Prefix => write!(fmt, "Method ARN must start with arn:"),
+            Self::Service(service) => write!(fmt, "Method ARN is not for execute-api: {service}"),
+            Self::Missing(name) => write!(fmt, "Method ARN is missing the {name}"),
+            Self::Method(method) => write!(fmt, "Method ARN has an unknown method: {method}"),
+        }
+    }
+}
+
+impl Error for MethodArnError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_arn_round_trips() {
+        for method_arn in [
+            "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET",
+            "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET/",
+            "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET/users",
+            "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET/users/123",
+            "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/*/users/*",
+            "arn:aws-cn:execute-api:cn-north-1:123456789012:abcdef1234/*/*",
+        ] {
+            assert_eq!(
+                method_arn.parse::<MethodArn>().unwrap().to_string(),
+                method_arn
+            );
+        }
+    }
+
+    #[test]
+    fn method_arn_keeps_whether_it_has_a_resource() {
+        let method_arn = |resource: &str| {
+            format!("arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/GET{resource}")
+                .parse::<MethodArn>()
+                .unwrap()
+                .path
+        };
+
+        assert_eq!(method_arn(""), None);
+        assert_eq!(method_arn("/"), Some(vec![]));
+        assert_eq!(
+            method_arn("/users/123"),
+            Some(vec!["users".to_string(), "123".to_string()])
+        );
+    }
+
+    fn arn(resource: &str) -> MethodArn {
+        format!("arn:aws:execute-api:ap-southeast-1:123456789012:{resource}")
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn wildcards_match_any_stage_method_or_resource() {
+        let method_arn = arn("abcdef1234/dev/GET/users/123");
+
+        for pattern in [
+            "abcdef1234/dev/GET/users/123",
+            "abcdef1234/*/GET/users/123",
+            "abcdef1234/dev/*/users/123",
+            "abcdef1234/dev/GET/*",
+            "abcdef1234/*/*/*",
+            "abcdef1234/dev/GET/users/12*",
+            "abcdef1234/dev/GET/users/12?",
+            "abcdef1234/d?v/GET/users/123",
+        ] {
+            assert!(arn(pattern).matches(&method_arn), "{pattern}");
+        }
+
+        // Like IAM, `*` also matches across path segments
+        assert!(arn("abcdef1234/dev/GET/*").matches(&arn("abcdef1234/dev/GET/users/123/posts")));
+    }
+
+    #[test]
+    fn wildcards_do_not_match_other_resources() {
+        let method_arn = arn("abcdef1234/dev/GET/users/123");
+
+        for pattern in [
+            "abcdef1234/prod/GET/users/123",
+            "abcdef1234/dev/POST/users/123",
+            "abcdef1234/dev/GET/users/45*",
+            "abcdef1234/dev/GET/posts/*",
+            "abcdef1234/dev/GET/users/12",
+            "abcdef1234/dev/GET/users/123/*",
+            "other12345/*/*/*",
+        ] {
+            assert!(!arn(pattern).matches(&method_arn), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn other_account_or_region_is_not_matched() {
+        let method_arn = arn("abcdef1234/dev/GET/users/123");
+
+        for pattern in [
+            "arn:aws:execute-api:ap-southeast-1:999999999999:abcdef1234/*/*/*",
+            "arn:aws:execute-api:us-east-1:123456789012:abcdef1234/*/*/*",
+            "arn:aws-cn:execute-api:ap-southeast-1:123456789012:abcdef1234/*/*/*",
+        ] {
+            let pattern = pattern.parse::<MethodArn>().unwrap();
+            assert!(!pattern.matches(&method_arn), "{pattern}");
+        }
+
+        // Unless the account and region are wildcards themselves
+        let pattern = "arn:aws:execute-api:*:*:abcdef1234/*/*/*"
+            .parse::<MethodArn>()
+            .unwrap();
+
+        assert!(pattern.matches(&method_arn));
+    }
+
+    #[test]
+    fn invalid_method_arn_is_rejected() {
+        assert_eq!(
+            "arn:aws:lambda:ap-southeast-1:123456789012:abcdef1234/dev/GET".parse::<MethodArn>(),
+            Err(MethodArnError::Service("lambda".to_string()))
+        );
+        assert_eq!(
+            "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev".parse::<MethodArn>(),
+            Err(MethodArnError::Missing("method"))
+        );
+        assert_eq!(
+            "arn:aws:execute-api:ap-southeast-1:123456789012:abcdef1234/dev/FETCH"
+                .parse::<MethodArn>(),
+            Err(MethodArnError::Method("FETCH".to_string()))
+        );
     }
 }
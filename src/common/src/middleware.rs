@@ -0,0 +1,629 @@
+use crate::{
+    api_handler, api_request::ApiRequest, common_tracing::Logger, cors, ApiResponse, CorsPolicy,
+};
+use aws_lambda_events::http::HeaderMap;
+use lambda_runtime::{
+    tower::{Layer, Service},
+    tracing::{error, info},
+    LambdaEvent,
+};
+use std::{
+    future::{self, Future},
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+// Every API service in the pipeline takes a LambdaEvent and produces an ApiResponse, whose
+// request_id is filled in by run_api_service at the end so that responses need not borrow the event
+pub type ApiResult = Result<ApiResponse<'static>, anyhow::Error>;
+
+pub type ApiFuture = Pin<Box<dyn Future<Output = ApiResult>>>;
+
+// Logs the event with sensitive data hidden before passing it on
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LogLayer;
+
+impl<S> Layer<S> for LogLayer {
+    type Service = LogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LogService(inner)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LogService<S>(S);
+
+impl<Req, S> Service<LambdaEvent<Req>> for LogService<S>
+where
+    Req: Logger,
+    S: Service<LambdaEvent<Req>, Response = ApiResponse<'static>, Error = anyhow::Error>,
+{
+    type Response = ApiResponse<'static>;
+    type Error = anyhow::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, event: LambdaEvent<Req>) -> Self::Future {
+        if let Err(err) = event.payload.log() {
+            error!(error = err.to_string());
+        }
+
+        self.0.call(event)
+    }
+}
+
+// Turns CommonError, LoadError and unknown errors into error responses, so that the layers wrapping
+// this one, e.g. CorsLayer, also apply to error responses
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ErrorLayer;
+
+impl<S> Layer<S> for ErrorLayer {
+    type Service = ErrorService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ErrorService(inner)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ErrorService<S>(S);
+
+impl<Req, S> Service<LambdaEvent<Req>> for ErrorService<S>
+where
+    S: Service<LambdaEvent<Req>, Response = ApiResponse<'static>, Error = anyhow::Error>,
+    S::Future: 'static,
+{
+    type Response = ApiResponse<'static>;
+    type Error = anyhow::Error;
+    type Future = ApiFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, event: LambdaEvent<Req>) -> Self::Future {
+        let fut = self.0.call(event);
+        Box::pin(async { Ok(api_handler::into_api_resp(fut.await, "")) })
+    }
+}
+
+// Answers preflight requests directly and adds the CORS headers to the other responses
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CorsLayer(pub CorsPolicy<'static>);
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = CorsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorsService {
+            inner,
+            cors_policy: self.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CorsService<S> {
+    inner: S,
+    cors_policy: CorsPolicy<'static>,
+}
+
+impl<Req, S> Service<LambdaEvent<Req>> for CorsService<S>
+where
+    Req: ApiRequest,
+    S: Service<LambdaEvent<Req>, Response = ApiResponse<'static>, Error = anyhow::Error>,
+    S::Future: 'static,
+{
+    type Response = ApiResponse<'static>;
+    type Error = anyhow::Error;
+    type Future = ApiFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, event: LambdaEvent<Req>) -> Self::Future {
+        if let Some(api_resp) = self.cors_policy.preflight(&event.payload, "") {
+            return Box::pin(future::ready(Ok(api_resp)));
+        }
+
+        let cors_headers = self.cors_policy.response_headers(&event.payload);
+        let fut = self.inner.call(event);
+
+        Box::pin(async move {
+            let mut api_resp = fut.await?;
+
+            if let Some(cors_headers) = cors_headers {
+                cors::merge_headers(&mut api_resp.headers, cors_headers);
+            }
+
+            Ok(api_resp)
+        })
+    }
+}
+
+// Logs how long the wrapped services take to respond
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimingLayer;
+
+impl<S> Layer<S> for TimingLayer {
+    type Service = TimingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimingService(inner)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimingService<S>(S);
+
+impl<Req, S> Service<LambdaEvent<Req>> for TimingService<S>
+where
+    S: Service<LambdaEvent<Req>, Response = ApiResponse<'static>, Error = anyhow::Error>,
+    S::Future: 'static,
+{
+    type Response = ApiResponse<'static>;
+    type Error = anyhow::Error;
+    type Future = ApiFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, event: LambdaEvent<Req>) -> Self::Future {
+        let start = Instant::now();
+        let fut = self.0.call(event);
+
+        Box::pin(async move {
+            let result = fut.await;
+            // Logged as u64, as u128 fields are logged as strings
+            let elapsed_ms = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+            info!(elapsed_ms);
+            result
+        })
+    }
+}
+
+// Adds the given headers to every response which has not set them yet
+#[derive(Clone, Debug, Default)]
+pub struct HeadersLayer(pub HeaderMap);
+
+impl<S> Layer<S> for HeadersLayer {
+    type Service = HeadersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HeadersService {
+            inner,
+            headers: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HeadersService<S> {
+    inner: S,
+    headers: HeaderMap,
+}
+
+impl<Req, S> Service<LambdaEvent<Req>> for HeadersService<S>
+where
+    S: Service<LambdaEvent<Req>, Response = ApiResponse<'static>, Error = anyhow::Error>,
+    S::Future: 'static,
+{
+    type Response = ApiResponse<'static>;
+    type Error = anyhow::Error;
+    type Future = ApiFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, event: LambdaEvent<Req>) -> Self::Future {
+        let headers = self.headers.clone();
+        let fut = self.inner.call(event);
+
+        Box::pin(async move {
+            let mut api_resp = fut.await?;
+
+            for k in headers.keys() {
+                if !api_resp.headers.contains_key(k) {
+                    for v in headers.get_all(k) {
+                        api_resp.headers.append(k, v.clone());
+                    }
+                }
+            }
+
+            Ok(api_resp)
+        })
+    }
+}
+
+// Rejects events before they reach the wrapped services, e.g. for auth checks, rate limiting or
+// idempotency. The guard gets the event and returns a future which must not borrow it, so that it
+// can call e.g. DynamoDB with what it takes from the event, e.g.
+// GuardLayer(|event: &LambdaEvent<ApiGatewayProxyRequest>| {
+//     let key = event.payload.headers.get("Idempotency-Key").cloned();
+//     async move { check_idempotency_key(key).await }
+// })
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GuardLayer<F>(pub F);
+
+impl<S, F: Clone> Layer<S> for GuardLayer<F> {
+    type Service = GuardService<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GuardService {
+            inner,
+            guard: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GuardService<S, F> {
+    inner: S,
+    guard: F,
+}
+
+impl<Req, S, F, Fut, E> Service<LambdaEvent<Req>> for GuardService<S, F>
+where
+    Req: 'static,
+    S: Service<LambdaEvent<Req>, Response = ApiResponse<'static>, Error = anyhow::Error>
+        + Clone
+        + 'static,
+    S::Future: 'static,
+    F: Fn(&LambdaEvent<Req>) -> Fut,
+    Fut: Future<Output = Result<(), E>> + 'static,
+    E: Into<anyhow::Error>,
+{
+    type Response = ApiResponse<'static>;
+    type Error = anyhow::Error;
+    type Future = ApiFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, event: LambdaEvent<Req>) -> Self::Future {
+        let guard = (self.guard)(&event);
+
+        // The inner service made ready by poll_ready is the one to call once the guard passes
+        let inner = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, inner);
+
+        Box::pin(async move {
+            guard.await.map_err(Into::into)?;
+            inner.call(event).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error_code::CommonErrorCode, CommonError};
+    use aws_lambda_events::{
+        apigw::ApiGatewayProxyRequest,
+        http::{
+            header::{
+                ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD, CACHE_CONTROL, ORIGIN,
+                VARY,
+            },
+            HeaderValue, Method,
+        },
+    };
+    use lambda_runtime::{
+        service_fn,
+        tower::{util::ServiceFn, ServiceBuilder, ServiceExt},
+        tracing::subscriber::{self, util::SubscriberInitExt},
+    };
+    use std::{
+        future::Ready,
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+    use tokio::runtime::Builder;
+
+    const CORS_POLICY: CorsPolicy<'static> = CorsPolicy {
+        allowed_origins: &["https://darkord.com"],
+        ..CorsPolicy::DEFAULT
+    };
+
+    type Calls = Arc<Mutex<Vec<&'static str>>>;
+
+    type GuardFuture = Pin<Box<dyn Future<Output = Result<(), CommonError>>>>;
+
+    fn event(method: Method) -> LambdaEvent<ApiGatewayProxyRequest> {
+        let mut event = ApiGatewayProxyRequest {
+            http_method: method,
+            ..Default::default()
+        };
+
+        event
+            .headers
+            .insert(ORIGIN, HeaderValue::from_static("https://darkord.com"));
+
+        LambdaEvent::new(event, lambda_runtime::Context::default())
+    }
+
+    // Records that it was called and responds with the given result
+    fn handler(
+        calls: &Calls,
+        result: fn() -> ApiResult,
+    ) -> ServiceFn<impl FnMut(LambdaEvent<ApiGatewayProxyRequest>) -> Ready<ApiResult> + Clone>
+    {
+        let calls = calls.clone();
+
+        service_fn(move |_event: LambdaEvent<ApiGatewayProxyRequest>| {
+            calls.lock().unwrap().push("handler");
+            future::ready(result())
+        })
+    }
+
+    // An async guard which records that it was called and rejects the event if is_rejected
+    fn guard(
+        calls: &Calls,
+        name: &'static str,
+        is_rejected: bool,
+    ) -> GuardLayer<impl Fn(&LambdaEvent<ApiGatewayProxyRequest>) -> GuardFuture + Clone> {
+        let calls = calls.clone();
+
+        GuardLayer(move |_event: &LambdaEvent<ApiGatewayProxyRequest>| {
+            let calls = calls.clone();
+
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                calls.lock().unwrap().push(name);
+
+                if is_rejected {
+                    Err(CommonError::from(CommonErrorCode::Unauthorized))
+                } else {
+                    Ok(())
+                }
+            }) as GuardFuture
+        })
+    }
+
+    fn call<S>(service: S, event: LambdaEvent<ApiGatewayProxyRequest>) -> ApiResult
+    where
+        S: Service<
+            LambdaEvent<ApiGatewayProxyRequest>,
+            Response = ApiResponse<'static>,
+            Error = anyhow::Error,
+        >,
+    {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(service.oneshot(event))
+    }
+
+    fn ok() -> ApiResult {
+        Ok(ApiResponse::default())
+    }
+
+    fn fail() -> ApiResult {
+        Err(CommonError::from(CommonErrorCode::Forbidden).into())
+    }
+
+    #[test]
+    fn guards_run_in_order_before_the_handler() {
+        let calls = Calls::default();
+
+        let service = ServiceBuilder::new()
+            .layer(guard(&calls, "first", false))
+            .layer(guard(&calls, "second", false))
+            .service(handler(&calls, ok));
+
+        assert!(call(service, event(Method::GET)).is_ok());
+        assert_eq!(*calls.lock().unwrap(), ["first", "second", "handler"]);
+    }
+
+    #[test]
+    fn rejecting_guard_short_circuits() {
+        let calls = Calls::default();
+
+        let service = ServiceBuilder::new()
+            .layer(ErrorLayer)
+            .layer(guard(&calls, "first", true))
+            .layer(guard(&calls, "second", false))
+            .service(handler(&calls, ok));
+
+        let api_resp = call(service, event(Method::GET)).unwrap();
+
+        assert_eq!(api_resp.code, CommonErrorCode::Unauthorized.into());
+        assert_eq!(*calls.lock().unwrap(), ["first"]);
+    }
+
+    #[test]
+    fn sync_guard_is_a_ready_future() {
+        let calls = Calls::default();
+
+        let service = ServiceBuilder::new()
+            .layer(ErrorLayer)
+            .layer(GuardLayer(|event: &LambdaEvent<ApiGatewayProxyRequest>| {
+                future::ready(if event.payload.headers.contains_key(ORIGIN) {
+                    Err(CommonError::from(CommonErrorCode::Forbidden))
+                } else {
+                    Ok(())
+                })
+            }))
+            .service(handler(&calls, ok));
+
+        let api_resp = call(service, event(Method::GET)).unwrap();
+
+        assert_eq!(api_resp.code, CommonErrorCode::Forbidden.into());
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn error_inside_cors_gets_cors_headers() {
+        let calls = Calls::default();
+
+        let service = ServiceBuilder::new()
+            .layer(CorsLayer(CORS_POLICY))
+            .layer(ErrorLayer)
+            .service(handler(&calls, fail));
+
+        let api_resp = call(service, event(Method::GET)).unwrap();
+
+        assert_eq!(api_resp.code, CommonErrorCode::Forbidden.into());
+        assert_eq!(
+            api_resp.headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://darkord.com"
+        );
+        assert_eq!(api_resp.headers[VARY], "Origin");
+    }
+
+    #[test]
+    fn error_outside_cors_skips_cors_headers() {
+        let calls = Calls::default();
+
+        let service = ServiceBuilder::new()
+            .layer(ErrorLayer)
+            .layer(CorsLayer(CORS_POLICY))
+            .service(handler(&calls, fail));
+
+        let api_resp = call(service, event(Method::GET)).unwrap();
+
+        assert_eq!(api_resp.code, CommonErrorCode::Forbidden.into());
+        assert!(api_resp.headers.is_empty());
+    }
+
+    #[test]
+    fn unknown_error_becomes_internal_server_error() {
+        let calls = Calls::default();
+
+        let service = ServiceBuilder::new()
+            .layer(ErrorLayer)
+            .service(handler(&calls, || Err(anyhow::anyhow!("database is down"))));
+
+        let api_resp = call(service, event(Method::GET)).unwrap();
+
+        assert_eq!(api_resp.code, CommonErrorCode::InternalServerError.into());
+        assert_eq!(api_resp.message, "");
+    }
+
+    #[test]
+    fn cors_preflight_is_answered_before_the_guards() {
+        let calls = Calls::default();
+
+        let service = ServiceBuilder::new()
+            .layer(CorsLayer(CORS_POLICY))
+            .layer(guard(&calls, "auth", true))
+            .service(handler(&calls, ok));
+
+        let mut event = event(Method::OPTIONS);
+        event.payload.headers.insert(
+            ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("POST"),
+        );
+
+        let api_resp = call(service, event).unwrap();
+
+        assert_eq!(api_resp.code, CommonErrorCode::NoContent.into());
+        assert_eq!(
+            api_resp.headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://darkord.com"
+        );
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn headers_layer_keeps_the_handler_headers() {
+        let calls = Calls::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+
+        let service = ServiceBuilder::new()
+            .layer(HeadersLayer(headers))
+            .service(handler(&calls, || {
+                let mut api_resp = ApiResponse::default();
+                api_resp
+                    .headers
+                    .insert(CACHE_CONTROL, HeaderValue::from_static("max-age=60"));
+
+                Ok(api_resp)
+            }));
+
+        let api_resp = call(service, event(Method::GET)).unwrap();
+
+        assert_eq!(api_resp.headers[CACHE_CONTROL], "max-age=60");
+        assert_eq!(api_resp.headers[VARY], "Accept-Encoding");
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn timing_layer_logs_the_elapsed_time() {
+        let calls = Calls::default();
+        let logs = LogBuffer::default();
+
+        let _guard = subscriber::fmt()
+            .json()
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish()
+            .set_default();
+
+        let service = ServiceBuilder::new()
+            .layer(TimingLayer)
+            .service(handler(&calls, ok));
+
+        assert!(call(service, event(Method::GET)).is_ok());
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let log = serde_json::from_str::<serde_json::Value>(logs.trim()).unwrap();
+
+        assert!(log["fields"]["elapsed_ms"].is_u64(), "{logs}");
+    }
+
+    #[test]
+    fn timing_and_log_layers_pass_results_through() {
+        let calls = Calls::default();
+
+        let service = ServiceBuilder::new()
+            .layer(LogLayer)
+            .layer(TimingLayer)
+            .service(handler(&calls, fail));
+
+        let err = call(service, event(Method::GET)).unwrap_err();
+
+        assert_eq!(
+            err.downcast::<CommonError>().unwrap().code,
+            CommonErrorCode::Forbidden.into()
+        );
+
+        let service = ServiceBuilder::new()
+            .layer(TimingLayer)
+            .layer(LogLayer)
+            .service(handler(&calls, ok));
+
+        assert_eq!(
+            call(service, event(Method::GET)).unwrap().code,
+            CommonErrorCode::Ok.into()
+        );
+        assert_eq!(*calls.lock().unwrap(), ["handler", "handler"]);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{common_enums::OtpAlgorithm, verification_code::ALPHANUMERIC};
+use anyhow::{Context as _, Result};
+use hmac::{digest::KeyInit, Hmac, Mac};
+use lambda_runtime::Context;
+use scrypt::password_hash::rand_core::{OsRng, RngCore};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::{
+    fmt::{self, Debug, Formatter},
+    panic::Location,
+};
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// RFC 4226 counter based one-time passwords
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hotp {
+    pub secret: Vec<u8>,
+    pub algorithm: OtpAlgorithm,
+    pub digit_count: u32,
+
+    // Counters after the expected one also accepted, for codes generated but never submitted
+    pub look_ahead: u64,
+}
+
+impl Hotp {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            algorithm: OtpAlgorithm::default(),
+            digit_count: 6,
+            look_ahead: 0,
+        }
+    }
+
+    pub fn generate(&self, counter: u64) -> String {
+        gen_code(&self.secret, self.algorithm, self.digit_count, counter)
+    }
+
+    // Returns the matched counter, after which the next expected counter has to be stored
+    pub fn verify(&self, code: &str, counter: u64) -> Option<u64> {
+        (counter..=counter.saturating_add(self.look_ahead))
+            .find(|&counter| is_code_eq(&self.generate(counter), code))
+    }
+
+    pub fn provisioning_uri(&self, issuer: &str, account_name: &str, counter: u64) -> String {
+        format!(
+            "{}&counter={counter}",
+            provisioning_uri(
+                "hotp",
+                issuer,
+                account_name,
+                &self.secret,
+                self.algorithm,
+                self.digit_count
+            )
+        )
+    }
+}
+
+// The secret is masked, so that logging the OTP settings does not leak it
+impl Debug for Hotp {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Hotp")
+            .field("secret", &format_args!("***"))
+            .field("algorithm", &self.algorithm)
+            .field("digit_count", &self.digit_count)
+            .field("look_ahead", &self.look_ahead)
+            .finish()
+    }
+}
+
+// RFC 6238 time based one-time passwords
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Totp {
+    pub secret: Vec<u8>,
+    pub algorithm: OtpAlgorithm,
+    pub digit_count: u32,
+
+    // Seconds each code stays valid for
+    pub period: u64,
+
+    // Periods before and after the current one also accepted, for clock drift between devices
+    pub drift: u64,
+}
+
+impl Totp {
+    // The defaults every authenticator app supports
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            algorithm: OtpAlgorithm::default(),
+            digit_count: 6,
+            period: 30,
+            drift: 1,
+        }
+    }
+
+    pub fn generate(&self) -> Result<String> {
+        let now = crate::get_current_timestamp()
+            .call()
+            .context(Location::caller())?;
+
+        Ok(self.generate_at(now))
+    }
+
+    pub fn generate_at(&self, timestamp: u64) -> String {
+        gen_code(
+            &self.secret,
+            self.algorithm,
+            self.digit_count,
+            self.time_step(timestamp),
+        )
+    }
+
+    // Returns the matched time step, which has to be stored so that the same code cannot be
+    // submitted twice, i.e. codes with a time step up to the stored one are to be rejected
+    pub fn verify(&self, code: &str) -> Result<Option<u64>> {
+        let now = crate::get_current_timestamp()
+            .call()
+            .context(Location::caller())?;
+
+        Ok(self.verify_at(code, now))
+    }
+
+    pub fn verify_at(&self, code: &str, timestamp: u64) -> Option<u64> {
+        let time_step = self.time_step(timestamp);
+
+        (time_step.saturating_sub(self.drift)..=time_step.saturating_add(self.drift)).find(
+            |&time_step| {
+                is_code_eq(
+                    &gen_code(&self.secret, self.algorithm, self.digit_count, time_step),
+                    code,
+                )
+            },
+        )
+    }
+
+    // To be shown as a QR code for authenticator apps to scan
+    pub fn provisioning_uri(&self, issuer: &str, account_name: &str) -> String {
+        format!(
+            "{}&period={}",
+            provisioning_uri(
+                "totp",
+                issuer,
+                account_name,
+                &self.secret,
+                self.algorithm,
+                self.digit_count
+            ),
+            self.period
+        )
+    }
+
+    fn time_step(&self, timestamp: u64) -> u64 {
+        timestamp / self.period.max(1)
+    }
+}
+
+impl Debug for Totp {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Totp")
+            .field("secret", &format_args!("***"))
+            .field("algorithm", &self.algorithm)
+            .field("digit_count", &self.digit_count)
+            .field("period", &self.period)
+            .field("drift", &self.drift)
+            .finish()
+    }
+}
+
+// 160 bits as recommended by RFC 4226
+pub fn gen_otp_secret() -> Vec<u8> {
+    let mut secret = vec![0; 20];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+// Returns the codes to show to the user once, e.g. ABCDE-FGHJK, and their hashes to store. The
+// codes are hashed on the blocking pool, giving up when the Lambda function is about to time out
+pub async fn gen_recovery_codes(
+    code_count: usize,
+    context: &Context,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut codes = Vec::with_capacity(code_count);
+    let mut hashed_codes = Vec::with_capacity(code_count);
+
+    for _ in 0..code_count {
+        let code = crate::pick_secret_chars(10, ALPHANUMERIC);
+
+        hashed_codes.push(
+            crate::hash_secret_async(&code, context)
+                .await
+                .context(Location::caller())?,
+        );
+
+        codes.push(format!("{}-{}", &code[..5], &code[5..]));
+    }
+
+    Ok((codes, hashed_codes))
+}
+
+// Returns the index of the matched hash, which has to be removed so that the code is used only
+// once. Case, spaces and dashes are ignored as users tend to type the codes in by hand
+pub async fn verify_recovery_code(
+    code: &str,
+    hashed_codes: &[String],
+    context: &Context,
+) -> Result<Option<usize>> {
+    let code = code
+        .chars()
+        .filter(|char| !char.is_whitespace() && *char != '-')
+        .collect::<String>()
+        .to_uppercase();
+
+    for (i, hashed_code) in hashed_codes.iter().enumerate() {
+        if crate::verify_secret_async(&code, hashed_code, context)
+            .await
+            .context(Location::caller())?
+        {
+            return Ok(Some(i));
+        }
+    }
+
+    Ok(None)
+}
+
+fn gen_code(secret: &[u8], algorithm: OtpAlgorithm, digit_count: u32, counter: u64) -> String {
+    let counter = counter.to_be_bytes();
+
+    let hash = match algorithm {
+        OtpAlgorithm::Sha1 => sign::<Hmac<Sha1>>(secret, &counter),
+        OtpAlgorithm::Sha256 => sign::<Hmac<Sha256>>(secret, &counter),
+        OtpAlgorithm::Sha512 => sign::<Hmac<Sha512>>(secret, &counter),
+    };
+
+    // Dynamic truncation, which takes 31 bits at an offset given by the last 4 bits of the hash
+    let offset = usize::from(hash[hash.len() - 1] & 0xf);
+
+    let code = u64::from(
+        u32::from_be_bytes([
+            hash[offset],
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]) & 0x7fff_ffff,
+    );
+
+    let code = 10u64
+        .checked_pow(digit_count)
+        .map_or(code, |modulus| code % modulus);
+
+    format!("{code:0width$}", width = digit_count as usize)
+}
+
+fn sign<M: Mac + KeyInit>(secret: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this never fails
+    let mut mac = <M as Mac>::new_from_slice(secret).unwrap();
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Takes the same time however many chars match, so that codes cannot be guessed char by char
+fn is_code_eq(expected_code: &str, code: &str) -> bool {
+    expected_code.len() == code.len()
+        && expected_code
+            .bytes()
+            .zip(code.bytes())
+            .fold(0, |diff, (expected_byte, byte)| {
+                diff | (expected_byte ^ byte)
+            })
+            == 0
+}
+
+fn provisioning_uri(
+    otp_type: &str,
+    issuer: &str,
+    account_name: &str,
+    secret: &[u8],
+    algorithm: OtpAlgorithm,
+    digit_count: u32,
+) -> String {
+    // Authenticator apps expect %20 rather than + for spaces
+    let encode = |value: &str| {
+        form_urlencoded::byte_serialize(value.as_bytes())
+            .collect::<String>()
+            .replace('+', "%20")
+    };
+
+    format!(
+        "otpauth://{otp_type}/{}:{}?secret={}&issuer={}&algorithm={}&digits={digit_count}",
+        encode(issuer),
+        encode(account_name),
+        encode_base32(secret),
+        encode(issuer),
+        algorithm.as_str()
+    )
+}
+
+// RFC 4648 base32 without padding, as used for otpauth secrets
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut base32 = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer = 0u16;
+    let mut bit_count = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | u16::from(byte);
+        bit_count += 8;
+
+        while bit_count >= 5 {
+            bit_count -= 5;
+            base32.push(char::from(
+                BASE32_ALPHABET[usize::from((buffer >> bit_count) & 0x1f)],
+            ));
+        }
+    }
+
+    if bit_count > 0 {
+        base32.push(char::from(
+            BASE32_ALPHABET[usize::from((buffer << (5 - bit_count)) & 0x1f)],
+        ));
+    }
+
+    base32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Builder;
+
+    const SHA1_SECRET: &[u8] = b"12345678901234567890";
+    const SHA256_SECRET: &[u8] = b"12345678901234567890123456789012";
+    const SHA512_SECRET: &[u8] =
+        b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    // RFC 4226 Appendix D
+    #[test]
+    fn hotp_matches_the_rfc_test_vectors() {
+        let hotp = Hotp::new(SHA1_SECRET);
+
+        for (counter, code) in [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let counter = counter as u64;
+
+            assert_eq!(hotp.generate(counter), code);
+            assert_eq!(hotp.verify(code, counter), Some(counter));
+        }
+    }
+
+    // RFC 6238 Appendix B
+    #[test]
+    fn totp_matches_the_rfc_test_vectors() {
+        for (timestamp, sha1_code, sha256_code, sha512_code) in [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ] {
+            for (algorithm, secret, code) in [
+                (OtpAlgorithm::Sha1, SHA1_SECRET, sha1_code),
+                (OtpAlgorithm::Sha256, SHA256_SECRET, sha256_code),
+                (OtpAlgorithm::Sha512, SHA512_SECRET, sha512_code),
+            ] {
+                let totp = Totp {
+                    algorithm,
+                    digit_count: 8,
+                    ..Totp::new(secret)
+                };
+
+                assert_eq!(totp.generate_at(timestamp), code);
+                assert_eq!(totp.verify_at(code, timestamp), Some(timestamp / 30));
+            }
+        }
+    }
+
+    #[test]
+    fn debug_masks_the_secret() {
+        assert_eq!(
+            format!("{:?}", Hotp::new(SHA1_SECRET)),
+            "Hotp { secret: ***, algorithm: Sha1, digit_count: 6, look_ahead: 0 }"
+        );
+        assert_eq!(
+            format!("{:?}", Totp::new(SHA1_SECRET)),
+            "Totp { secret: ***, algorithm: Sha1, digit_count: 6, period: 30, drift: 1 }"
+        );
+    }
+
+    #[test]
+    fn recovery_code_is_verified_however_it_is_typed() {
+        let mut context = Context::default();
+        context.deadline = crate::extend_current_timestamp()
+            .unit(crate::Unit::Milliseconds)
+            .minutes(1i64)
+            .call()
+            .unwrap();
+
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let (codes, hashed_codes) = gen_recovery_codes(2, &context).await.unwrap();
+
+                assert_eq!(codes.len(), 2);
+                assert_eq!(codes[1].len(), 11);
+
+                let code = format!(" {} ", codes[1].to_lowercase().replace('-', ""));
+
+                assert_eq!(
+                    verify_recovery_code(&code, &hashed_codes, &context)
+                        .await
+                        .unwrap(),
+                    Some(1)
+                );
+                assert_eq!(
+                    verify_recovery_code("AAAAA-AAAAA", &hashed_codes, &context)
+                        .await
+                        .unwrap(),
+                    None
+                );
+            });
+    }
+}
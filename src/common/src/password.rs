@@ -0,0 +1,405 @@
+use argon2::{Argon2, Version};
+use hmac::{Hmac, Mac};
+use pbkdf2::Pbkdf2;
+use scrypt::{
+    password_hash::{
+        self, rand_core::OsRng, PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString,
+    },
+    Scrypt,
+};
+use sha2::Sha256;
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+};
+
+const HASH_LEN: usize = 32;
+
+// The PHC param recording which pepper a hash was made with, which is absent for unpeppered hashes
+const PEPPER_PARAM: &str = "pepper";
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PasswordParams {
+    Scrypt {
+        log_n: u8,
+        r: u32,
+        p: u32,
+    },
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+    Pbkdf2 {
+        rounds: u32,
+    },
+}
+
+impl PasswordParams {
+    // The params hash_secret has always used
+    pub const SCRYPT: Self = Self::Scrypt {
+        log_n: 15,
+        r: 8,
+        p: 1,
+    };
+
+    // OWASP recommendations, which take roughly the same time as SCRYPT on a Lambda function
+    pub const ARGON2ID: Self = Self::Argon2id {
+        m_cost: 19 * 1024,
+        t_cost: 2,
+        p_cost: 1,
+    };
+
+    pub const PBKDF2: Self = Self::Pbkdf2 { rounds: 600_000 };
+
+    const fn algorithm(&self) -> &'static str {
+        match self {
+            Self::Scrypt { .. } => "scrypt",
+            Self::Argon2id { .. } => "argon2id",
+            Self::Pbkdf2 { .. } => "pbkdf2-sha256",
+        }
+    }
+}
+
+impl Default for PasswordParams {
+    fn default() -> Self {
+        Self::SCRYPT
+    }
+}
+
+// Hashes into PHC strings which record the algorithm and params used, so that verify keeps working
+// for hashes made with older params while needs_rehash tells which of them to upgrade on login
+#[derive(Clone, Default, Hash, PartialEq, Eq)]
+pub struct PasswordHasher {
+    params: PasswordParams,
+
+    // A secret kept outside the database, e.g. in SSM, which is mixed into every password so that
+    // a leaked database alone is not enough to crack them. Its id is recorded in each hash, so that
+    // hashes made with a previous pepper or with none can still be verified and then rehashed
+    pepper: Option<(String, Vec<u8>)>,
+
+    previous_peppers: Vec<(String, Vec<u8>)>,
+}
+
+impl PasswordHasher {
+    pub const fn new(params: PasswordParams) -> Self {
+        Self {
+            params,
+            pepper: None,
+            previous_peppers: vec![],
+        }
+    }
+
+    // The id is a short name made of [a-zA-Z0-9/+.-], e.g. v2
+    pub fn pepper(mut self, id: &str, pepper: &[u8]) -> Self {
+        self.pepper = Some((id.to_string(), pepper.to_vec()));
+        self
+    }
+
+    // A pepper which hashes may still be made with during a pepper rotation
+    pub fn previous_pepper(mut self, id: &str, pepper: &[u8]) -> Self {
+        self.previous_peppers
+            .push((id.to_string(), pepper.to_vec()));
+        self
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        let pepper = self
+            .pepper
+            .as_ref()
+            .map(|(id, pepper)| (id.as_str(), pepper.as_slice()));
+
+        let password = pepper_password(password, pepper)?;
+        let salt = SaltString::generate(&mut OsRng);
+
+        let hash = match self.params {
+            PasswordParams::Scrypt { log_n, r, p } => Scrypt.hash_password_customized(
+                &password,
+                None,
+                None,
+                scrypt::Params::new(log_n, r, p, HASH_LEN)
+                    .map_err(|err| PasswordError::Params(err.to_string()))?,
+                &salt,
+            ),
+            PasswordParams::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => Argon2::new(
+                argon2::Algorithm::Argon2id,
+                Version::V0x13,
+                argon2::Params::new(m_cost, t_cost, p_cost, Some(HASH_LEN))
+                    .map_err(|err| PasswordError::Params(err.to_string()))?,
+            )
+            .hash_password(&password, &salt),
+            PasswordParams::Pbkdf2 { rounds } => Pbkdf2.hash_password_customized(
+                &password,
+                Some(pbkdf2::Algorithm::Pbkdf2Sha256.ident()),
+                None,
+                pbkdf2::Params {
+                    rounds,
+                    output_length: HASH_LEN,
+                },
+                &salt,
+            ),
+        };
+
+        let mut hash = hash.map_err(PasswordError::Hash)?;
+
+        if let Some((id, _)) = pepper {
+            hash.params
+                .add_str(PEPPER_PARAM, id)
+                .map_err(|err| PasswordError::Params(err.to_string()))?;
+        }
+
+        Ok(hash.to_string())
+    }
+
+    // Picks the algorithm from the hash rather than from this hasher, so that any supported hash
+    // can be verified
+    pub fn verify(&self, password: &str, hash: &str) -> Result<(), PasswordError> {
+        let mut hash = PasswordHash::new(hash).map_err(PasswordError::Malformed)?;
+        let pepper = self.hash_pepper(&hash)?;
+        let password = pepper_password(password, pepper)?;
+
+        // The pepper param is ours, so the algorithms would reject it as unknown
+        hash.params = hash
+            .params
+            .iter()
+            .filter(|(name, _)| name.as_str() != PEPPER_PARAM)
+            .collect();
+
+        let result = match hash.algorithm.as_str() {
+            "scrypt" => Scrypt.verify_password(&password, &hash),
+            "argon2id" => Argon2::default().verify_password(&password, &hash),
+            "pbkdf2-sha256" => Pbkdf2.verify_password(&password, &hash),
+            algorithm => return Err(PasswordError::Algorithm(algorithm.to_string())),
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(password_hash::Error::Password) => Err(PasswordError::Mismatch),
+            Err(err) => Err(PasswordError::Hash(err)),
+        }
+    }
+
+    // Whether the hash was made with another algorithm, params or pepper than this hasher uses
+    pub fn needs_rehash(&self, hash: &str) -> Result<bool, PasswordError> {
+        let hash = PasswordHash::new(hash).map_err(PasswordError::Malformed)?;
+
+        if hash.algorithm.as_str() != self.params.algorithm()
+            || hash.params.get_str(PEPPER_PARAM) != self.pepper.as_ref().map(|(id, _)| id.as_str())
+        {
+            return Ok(true);
+        }
+
+        let is_param_eq = |name, value| hash.params.get_decimal(name) == Some(value);
+
+        let is_up_to_date = match self.params {
+            PasswordParams::Scrypt { log_n, r, p } => {
+                is_param_eq("ln", log_n.into()) && is_param_eq("r", r) && is_param_eq("p", p)
+            }
+            PasswordParams::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => {
+                hash.version == Some(Version::V0x13.into())
+                    && is_param_eq("m", m_cost)
+                    && is_param_eq("t", t_cost)
+                    && is_param_eq("p", p_cost)
+            }
+            PasswordParams::Pbkdf2 { rounds } => is_param_eq("i", rounds),
+        };
+
+        Ok(!is_up_to_date)
+    }
+
+    // The pepper named by the hash, or none for a hash made before peppers were used
+    fn hash_pepper(&self, hash: &PasswordHash<'_>) -> Result<Option<(&str, &[u8])>, PasswordError> {
+        let Some(hash_pepper_id) = hash.params.get_str(PEPPER_PARAM) else {
+            return Ok(None);
+        };
+
+        self.pepper
+            .iter()
+            .chain(&self.previous_peppers)
+            .find(|(id, _)| id == hash_pepper_id)
+            .map(|(id, pepper)| Some((id.as_str(), pepper.as_slice())))
+            .ok_or_else(|| PasswordError::UnknownPepper(hash_pepper_id.to_string()))
+    }
+}
+
+// Only the pepper ids are shown, so that logging a hasher does not leak the peppers
+impl Debug for PasswordHasher {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PasswordHasher")
+            .field("params", &self.params)
+            .field("pepper", &self.pepper.as_ref().map(|(id, _)| id))
+            .field(
+                "previous_peppers",
+                &self
+                    .previous_peppers
+                    .iter()
+                    .map(|(id, _)| id)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+fn pepper_password<'a>(
+    password: &'a str,
+    pepper: Option<(&str, &[u8])>,
+) -> Result<Cow<'a, [u8]>, PasswordError> {
+    let Some((_, pepper)) = pepper else {
+        return Ok(Cow::Borrowed(password.as_bytes()));
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(pepper)
+        .map_err(|_| PasswordError::Hash(password_hash::Error::Crypto))?;
+    mac.update(password.as_bytes());
+    Ok(Cow::Owned(mac.finalize().into_bytes().to_vec()))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PasswordError {
+    Params(String),
+    Hash(password_hash::Error),
+    Malformed(password_hash::Error),
+    Algorithm(String),
+    UnknownPepper(String),
+    Mismatch,
+}
+
+impl Display for PasswordError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Params(err) => write!(fmt, "Password hash params are invalid: {err}"),
+            Self::Hash(err) => write!(fmt, "Failed to hash the password: {err}"),
+            Self::Malformed(err) => write!(fmt, "Password hash is malformed: {err}"),
+            Self::Algorithm(algorithm) => {
+                write!(fmt, "Password hash algorithm is not supported: {algorithm}")
+            }
+            Self::UnknownPepper(id) => write!(fmt, "Password hash pepper is not known: {id}"),
+            Self::Mismatch => write!(fmt, "Password does not match"),
+        }
+    }
+}
+
+impl Error for PasswordError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Hash(err) | Self::Malformed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap params so that the tests stay fast
+    const PARAMS: PasswordParams = PasswordParams::Scrypt {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+
+    #[test]
+    fn hash_is_verified_with_each_algorithm() {
+        for params in [
+            PARAMS,
+            PasswordParams::Argon2id {
+                m_cost: 64,
+                t_cost: 1,
+                p_cost: 1,
+            },
+            PasswordParams::Pbkdf2 { rounds: 10 },
+        ] {
+            let hasher = PasswordHasher::new(params);
+            let hash = hasher.hash("password").unwrap();
+
+            assert_eq!(hasher.verify("password", &hash), Ok(()));
+            assert_eq!(
+                hasher.verify("Password", &hash),
+                Err(PasswordError::Mismatch)
+            );
+            assert_eq!(hasher.needs_rehash(&hash), Ok(false));
+        }
+    }
+
+    #[test]
+    fn pepper_id_is_recorded_in_the_hash() {
+        let hasher = PasswordHasher::new(PARAMS).pepper("v1", b"pepper");
+        let hash = hasher.hash("password").unwrap();
+
+        assert!(hash.contains("pepper=v1"));
+        assert_eq!(hasher.verify("password", &hash), Ok(()));
+        assert_eq!(hasher.needs_rehash(&hash), Ok(false));
+
+        // The pepper is part of the hash, not just a label on it
+        let hasher = PasswordHasher::new(PARAMS).pepper("v1", b"other pepper");
+
+        assert_eq!(
+            hasher.verify("password", &hash),
+            Err(PasswordError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn unpeppered_hash_is_verified_and_needs_rehash() {
+        let hash = PasswordHasher::new(PARAMS).hash("password").unwrap();
+        let hasher = PasswordHasher::new(PARAMS).pepper("v1", b"pepper");
+
+        assert_eq!(hasher.verify("password", &hash), Ok(()));
+        assert_eq!(hasher.needs_rehash(&hash), Ok(true));
+    }
+
+    #[test]
+    fn hash_with_a_previous_pepper_is_verified_and_needs_rehash() {
+        let hash = PasswordHasher::new(PARAMS)
+            .pepper("v1", b"pepper")
+            .hash("password")
+            .unwrap();
+
+        let hasher = PasswordHasher::new(PARAMS)
+            .pepper("v2", b"next pepper")
+            .previous_pepper("v1", b"pepper");
+
+        assert_eq!(hasher.verify("password", &hash), Ok(()));
+        assert_eq!(hasher.needs_rehash(&hash), Ok(true));
+
+        let hasher = PasswordHasher::new(PARAMS).pepper("v2", b"next pepper");
+
+        assert_eq!(
+            hasher.verify("password", &hash),
+            Err(PasswordError::UnknownPepper("v1".to_string()))
+        );
+    }
+
+    #[test]
+    fn debug_shows_pepper_ids_only() {
+        let hasher = PasswordHasher::new(PARAMS)
+            .pepper("v2", b"next pepper")
+            .previous_pepper("v1", b"pepper");
+
+        assert_eq!(
+            format!("{hasher:?}"),
+            "PasswordHasher { params: Scrypt { log_n: 4, r: 8, p: 1 }, pepper: Some(\"v2\"), \
+             previous_peppers: [\"v1\"] }"
+        );
+    }
+
+    #[test]
+    fn invalid_pepper_id_is_an_error() {
+        let hasher = PasswordHasher::new(PARAMS).pepper("v 1", b"pepper");
+
+        assert!(matches!(
+            hasher.hash("password"),
+            Err(PasswordError::Params(_))
+        ));
+    }
+}
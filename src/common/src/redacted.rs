@@ -0,0 +1,1173 @@
+use crate::{
+    common_enums::MaskStrategy,
+    redaction::{self, RedactionRules},
+    secret,
+};
+use optarg2chain::optarg_impl;
+use serde::ser::{
+    self, Impossible, Serialize, SerializeMap, SerializeSeq, SerializeStruct,
+    SerializeStructVariant, SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+    Serializer,
+};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+// Serializes any value with its sensitive entries masked the same way SensitiveData::hide does,
+// while the value is being serialized, so that the value itself is neither copied nor mutated, e.g.
+// serde_json::to_string(&Redacted::new(&event).json_keys(&["body"][..]).call())
+pub struct Redacted<'a, T> {
+    value: &'a T,
+    context: Context<'a>,
+}
+
+#[optarg_impl]
+impl<'a, T: Serialize> Redacted<'a, T> {
+    #[optarg_method(RedactedNewBuilder, call)]
+    pub fn new(
+        value: &'a T,
+        #[optarg_default] extra_sensitive_keys: &'a [&'a str],
+
+        // Keys removed only at the top level of the value rather than at any depth, e.g. the fields
+        // marked with #[sensitive]
+        #[optarg_default] sensitive_fields: &'a [&'a str],
+
+        // Keys whose string values hold JSON, e.g. the body of API Gateway events, which is parsed
+        // and redacted with the same rules. Strings which are not JSON, e.g. form or base64 bodies,
+        // are replaced with a placeholder, as there is no telling what they hold
+        #[optarg_default] json_keys: &'a [&'a str],
+
+        #[optarg_default] redaction_rules: Option<&'a RedactionRules>,
+    ) -> Self {
+        Self {
+            value,
+            context: Context {
+                redaction_rules: redaction_rules.unwrap_or_else(|| redaction::rules()),
+                extra_sensitive_keys,
+                sensitive_fields,
+                json_keys,
+                path: RefCell::default(),
+            },
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Redacted<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(RedactingSerializer {
+            inner: serializer,
+            context: &self.context,
+        })
+    }
+}
+
+struct Context<'a> {
+    redaction_rules: &'a RedactionRules,
+    extra_sensitive_keys: &'a [&'a str],
+    sensitive_fields: &'a [&'a str],
+    json_keys: &'a [&'a str],
+
+    // The keys from the root down to the entry being serialized, where struct fields are borrowed
+    path: RefCell<Vec<Cow<'static, str>>>,
+}
+
+impl Context<'_> {
+    // Serializes an entry with its key pushed onto the path, where f gets None for a removed entry
+    fn with_entry<T: ?Sized + Serialize, R, E: ser::Error>(
+        &self,
+        key: Cow<'static, str>,
+        value: &T,
+        f: impl FnOnce(Option<&Entry<'_, T>>) -> Result<R, E>,
+    ) -> Result<R, E> {
+        self.path.borrow_mut().push(key);
+        let result = self.entry(value).and_then(|entry| f(entry.as_ref()));
+        self.path.borrow_mut().pop();
+        result
+    }
+
+    fn entry<'e, T: ?Sized + Serialize, E: ser::Error>(
+        &'e self,
+        value: &'e T,
+    ) -> Result<Option<Entry<'e, T>>, E> {
+        // A secret is masked before anything else, so that none of the rules or detectors get to
+        // see its value
+        if let Err(ProbeError::Secret) = value.serialize(StrProbe(|_: &str| ())) {
+            return Ok(Some(Entry::Replaced("***".to_string())));
+        }
+
+        let rules = self.redaction_rules;
+        let path = self.path.borrow();
+        let key = path.last().map(|key| key.as_ref());
+
+        // The same order as RedactionRules::mask_strategy, with the extra sensitive keys and the
+        // sensitive fields always removed
+        let mask_strategy = key
+            .and_then(|key| rules.key_mask_strategy(key))
+            .or_else(|| rules.path_mask_strategy(&path))
+            .or_else(|| {
+                key.filter(|key| {
+                    self.extra_sensitive_keys
+                        .iter()
+                        .any(|sensitive_key| sensitive_key.eq_ignore_ascii_case(key))
+                        || path.len() == 1 && self.sensitive_fields.contains(key)
+                })
+                .map(|_| MaskStrategy::Remove)
+            })
+            .or_else(|| {
+                value
+                    .serialize(StrProbe(|value: &str| rules.value_mask_strategy(value)))
+                    .ok()
+                    .flatten()
+                    .flatten()
+            });
+
+        let is_json = key.is_some_and(|key| self.json_keys.contains(&key));
+        drop(path);
+
+        match mask_strategy {
+            Some(MaskStrategy::Remove) => Ok(None),
+            Some(mask_strategy) => {
+                let masked_value = match value
+                    .serialize(StrProbe(|value: &str| rules.mask_str(mask_strategy, value)))
+                {
+                    Ok(Some(masked_value)) => masked_value,
+                    _ => rules.mask_str(mask_strategy, &to_masked_secrets_json(value)?),
+                };
+
+                Ok(masked_value.map(Entry::Replaced))
+            }
+            None if is_json => {
+                let json = match value.serialize(StrProbe(|json: &str| {
+                    serde_json::from_str::<Value>(json).map_err(|_| json.len())
+                })) {
+                    Ok(Some(Ok(json))) => json,
+                    Ok(Some(Err(len))) => {
+                        return Ok(Some(Entry::Replaced(format!(
+                            "<non-JSON body, {len} bytes>"
+                        ))));
+                    }
+                    _ => {
+                        return Ok(Some(Entry::Shown(Shown {
+                            value,
+                            context: self,
+                        })));
+                    }
+                };
+
+                let redacted = Redacted {
+                    value: &json,
+                    context: Context {
+                        redaction_rules: rules,
+                        extra_sensitive_keys: self.extra_sensitive_keys,
+                        sensitive_fields: &[],
+                        json_keys: self.json_keys,
+                        path: RefCell::default(),
+                    },
+                };
+
+                Ok(Some(Entry::Replaced(
+                    serde_json::to_string(&redacted).map_err(E::custom)?,
+                )))
+            }
+            None => Ok(Some(Entry::Shown(Shown {
+                value,
+                context: self,
+            }))),
+        }
+    }
+}
+
+enum Entry<'a, T: ?Sized> {
+    // A masked value, or the redacted JSON of a string value holding JSON, or the placeholder of a
+    // string value which does not
+    Replaced(String),
+
+    Shown(Shown<'a, T>),
+}
+
+impl<T: ?Sized + Serialize> Serialize for Entry<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Replaced(value) => serializer.serialize_str(value),
+            Self::Shown(value) => value.serialize(serializer),
+        }
+    }
+}
+
+struct Shown<'a, T: ?Sized> {
+    value: &'a T,
+    context: &'a Context<'a>,
+}
+
+impl<T: ?Sized + Serialize> Serialize for Shown<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(RedactingSerializer {
+            inner: serializer,
+            context: self.context,
+        })
+    }
+}
+
+// Passes everything on to the inner serializer, with every entry of maps, structs, sequences and
+// tuples checked against the rules
+struct RedactingSerializer<'a, S> {
+    inner: S,
+    context: &'a Context<'a>,
+}
+
+impl<'a, S: Serializer> Serializer for RedactingSerializer<'a, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Compound<'a, S::SerializeSeq>;
+    type SerializeTuple = Compound<'a, S::SerializeTuple>;
+    type SerializeTupleStruct = Compound<'a, S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Compound<'a, S::SerializeTupleVariant>;
+    type SerializeMap = Compound<'a, S::SerializeMap>;
+    type SerializeStruct = Compound<'a, S::SerializeStruct>;
+    type SerializeStructVariant = Compound<'a, S::SerializeStructVariant>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_bool(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_i8(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_i16(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_i32(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_i64(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_i128(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_u8(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_u16(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_u32(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_u64(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_u128(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_f32(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_f64(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_char(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_str(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_some(&Shown {
+            value,
+            context: self.context,
+        })
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        self.inner
+            .serialize_unit_variant(name, variant_index, variant)
+    }
+
+    // Secrets are masked wherever they are, whatever the rules
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        if name == secret::NAME {
+            return self.inner.serialize_str("***");
+        }
+
+        self.inner.serialize_newtype_struct(
+            name,
+            &Shown {
+                value,
+                context: self.context,
+            },
+        )
+    }
+
+    // Serialized as {variant: value}, so the variant is checked like a key, and a removed value
+    // leaves an empty map behind like a removed map entry does
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        let Self { inner, context } = self;
+
+        context.with_entry(Cow::Borrowed(variant), value, |entry| match entry {
+            Some(entry) => inner.serialize_newtype_variant(name, variant_index, variant, entry),
+            None => inner.serialize_map(Some(0))?.end(),
+        })
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        let inner = self.inner.serialize_seq(len)?;
+        Ok(Compound::new(inner, self.context, None))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        let inner = self.inner.serialize_tuple(len)?;
+        Ok(Compound::new(inner, self.context, None))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        let inner = self.inner.serialize_tuple_struct(name, len)?;
+        Ok(Compound::new(inner, self.context, None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        let inner = self
+            .inner
+            .serialize_tuple_variant(name, variant_index, variant, len)?;
+
+        Ok(Compound::new(inner, self.context, Some(variant)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        let inner = self.inner.serialize_map(len)?;
+        Ok(Compound::new(inner, self.context, None))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        let inner = self.inner.serialize_struct(name, len)?;
+        Ok(Compound::new(inner, self.context, None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        let inner = self
+            .inner
+            .serialize_struct_variant(name, variant_index, variant, len)?;
+
+        Ok(Compound::new(inner, self.context, Some(variant)))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+struct Compound<'a, C> {
+    inner: C,
+    context: &'a Context<'a>,
+
+    // Of the next element of sequences and tuples
+    index: usize,
+
+    // Of map entries serialized with serialize_key and serialize_value rather than serialize_entry
+    key: Option<String>,
+
+    // Keeps the variant on the path until the compound is dropped
+    _variant: Option<VariantGuard<'a>>,
+}
+
+impl<'a, C> Compound<'a, C> {
+    fn new(inner: C, context: &'a Context<'a>, variant: Option<&'static str>) -> Self {
+        Self {
+            inner,
+            context,
+            index: 0,
+            key: None,
+            _variant: variant.map(|variant| {
+                context.path.borrow_mut().push(Cow::Borrowed(variant));
+                VariantGuard(context)
+            }),
+        }
+    }
+
+    fn next_key(&mut self) -> Cow<'static, str> {
+        self.index += 1;
+        Cow::Owned((self.index - 1).to_string())
+    }
+}
+
+// Pops the variant whether the compound ends or is dropped after an error, so that the path stays
+// balanced when the same Redacted value is serialized again
+struct VariantGuard<'a>(&'a Context<'a>);
+
+impl Drop for VariantGuard<'_> {
+    fn drop(&mut self) {
+        self.0.path.borrow_mut().pop();
+    }
+}
+
+// Removed elements are serialized as null, as they cannot be left out without shifting the elements
+// after them
+macro_rules! impl_serialize_elements {
+    ($($trait:ident::$method:ident),*) => {
+        $(
+            impl<C: $trait> $trait for Compound<'_, C> {
+                type Ok = C::Ok;
+                type Error = C::Error;
+
+                fn $method<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), C::Error> {
+                    let key = self.next_key();
+                    let Self { inner, context, .. } = self;
+
+                    context.with_entry(key, value, |entry| match entry {
+                        Some(entry) => inner.$method(entry),
+                        None => inner.$method(&()),
+                    })
+                }
+
+                fn end(self) -> Result<C::Ok, C::Error> {
+                    self.inner.end()
+                }
+            }
+        )*
+    };
+}
+
+impl_serialize_elements!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+macro_rules! impl_serialize_fields {
+    ($($trait:ident),*) => {
+        $(
+            impl<C: $trait> $trait for Compound<'_, C> {
+                type Ok = C::Ok;
+                type Error = C::Error;
+
+                fn serialize_field<T: ?Sized + Serialize>(
+                    &mut self,
+                    key: &'static str,
+                    value: &T,
+                ) -> Result<(), C::Error> {
+                    let Self { inner, context, .. } = self;
+
+                    context.with_entry(Cow::Borrowed(key), value, |entry| match entry {
+                        Some(entry) => inner.serialize_field(key, entry),
+                        None => inner.skip_field(key),
+                    })
+                }
+
+                fn end(self) -> Result<C::Ok, C::Error> {
+                    self.inner.end()
+                }
+            }
+        )*
+    };
+}
+
+impl_serialize_fields!(SerializeStruct, SerializeStructVariant);
+
+impl<C: SerializeMap> SerializeMap for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    // Held back until the value is known not to be removed
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), C::Error> {
+        self.key = Some(key_to_string(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), C::Error> {
+        let key = self.key.take().unwrap_or_default();
+        let Self { inner, context, .. } = self;
+
+        context.with_entry(Cow::Owned(key.clone()), value, |entry| match entry {
+            Some(entry) => inner.serialize_entry(&key, entry),
+            None => Ok(()),
+        })
+    }
+
+    fn serialize_entry<K: ?Sized + Serialize, V: ?Sized + Serialize>(
+        &mut self,
+        key: &K,
+        value: &V,
+    ) -> Result<(), C::Error> {
+        let Self { inner, context, .. } = self;
+
+        context.with_entry(
+            Cow::Owned(key_to_string(key)?),
+            value,
+            |entry| match entry {
+                // Passed on as is rather than as a string, with only a secret key masked
+                Some(entry) => inner.serialize_entry(
+                    &Shown {
+                        value: key,
+                        context,
+                    },
+                    entry,
+                ),
+                None => Ok(()),
+            },
+        )
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+// The JSON text of a value which gets masked as a whole, with the secrets within it masked first
+fn to_masked_secrets_json<T: ?Sized + Serialize, E: ser::Error>(value: &T) -> Result<String, E> {
+    let rules = RedactionRules::empty();
+    let context = Context {
+        redaction_rules: &rules,
+        extra_sensitive_keys: &[],
+        sensitive_fields: &[],
+        json_keys: &[],
+        path: RefCell::default(),
+    };
+
+    let mut json = vec![];
+
+    value
+        .serialize(RedactingSerializer {
+            inner: &mut serde_json::Serializer::new(&mut json),
+            context: &context,
+        })
+        .map_err(E::custom)?;
+
+    String::from_utf8(json).map_err(E::custom)
+}
+
+// Keys other than strings are turned into strings the same way serde_json does
+fn key_to_string<T: ?Sized + Serialize, E: ser::Error>(key: &T) -> Result<String, E> {
+    match key.serialize(StrProbe(str::to_string)) {
+        Ok(Some(key)) => Ok(key),
+        Err(ProbeError::Secret) => Ok("***".to_string()),
+        _ => match serde_json::to_value(key).map_err(E::custom)? {
+            Value::String(key) => Ok(key),
+            key => Ok(key.to_string()),
+        },
+    }
+}
+
+// Passes strings to the function and gives None for any other value, without serializing anything
+// when the value is a map, struct, sequence or tuple, or a secret which is never passed on
+struct StrProbe<F>(F);
+
+#[derive(Debug)]
+enum ProbeError {
+    NotStr,
+    Secret,
+}
+
+impl Display for ProbeError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotStr => write!(fmt, "Value is not a string"),
+            Self::Secret => write!(fmt, "Value is a secret"),
+        }
+    }
+}
+
+impl Error for ProbeError {}
+
+impl ser::Error for ProbeError {
+    fn custom<T: Display>(_msg: T) -> Self {
+        Self::NotStr
+    }
+}
+
+impl<R, F: FnOnce(&str) -> R> Serializer for StrProbe<F> {
+    type Ok = Option<R>;
+    type Error = ProbeError;
+    type SerializeSeq = Impossible<Option<R>, ProbeError>;
+    type SerializeTuple = Impossible<Option<R>, ProbeError>;
+    type SerializeTupleStruct = Impossible<Option<R>, ProbeError>;
+    type SerializeTupleVariant = Impossible<Option<R>, ProbeError>;
+    type SerializeMap = Impossible<Option<R>, ProbeError>;
+    type SerializeStruct = Impossible<Option<R>, ProbeError>;
+    type SerializeStructVariant = Impossible<Option<R>, ProbeError>;
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        Ok(Some((self.0)(v.encode_utf8(&mut [0; 4]))))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        Ok(Some((self.0)(v)))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        if name == secret::NAME {
+            return Err(ProbeError::Secret);
+        }
+
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(ProbeError::NotStr)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Err(ProbeError::NotStr)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(ProbeError::NotStr)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(ProbeError::NotStr)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Err(ProbeError::NotStr)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Err(ProbeError::NotStr)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(ProbeError::NotStr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common_enums::ValueDetector, Secret, SensitiveData};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{ser::Error as _, Serialize};
+    use serde_json::json;
+    use std::collections::{BTreeMap, HashMap};
+
+    const MASK_STRATEGIES: [MaskStrategy; 5] = [
+        MaskStrategy::Remove,
+        MaskStrategy::Replace,
+        MaskStrategy::LastFour,
+        MaskStrategy::PreserveLength,
+        MaskStrategy::Fingerprint,
+    ];
+
+    const EXTRA_SENSITIVE_KEYS: &[&str] = &["pin"];
+
+    fn rules(strategy: MaskStrategy) -> RedactionRules {
+        RedactionRules::default()
+            .mask(strategy)
+            .fingerprint_key(b"fingerprint key")
+            .key("card")
+            .path("/items/*")
+            .detect(ValueDetector::CardNumber)
+            .detect(ValueDetector::Email)
+    }
+
+    fn redact<T: Serialize>(value: &T, rules: &RedactionRules) -> Value {
+        serde_json::to_value(
+            Redacted::new(value)
+                .extra_sensitive_keys(EXTRA_SENSITIVE_KEYS)
+                .json_keys(&["body"][..])
+                .redaction_rules(Some(rules))
+                .call(),
+        )
+        .unwrap()
+    }
+
+    // What SensitiveData::hide makes of the value, with the JSON held by body hidden the same way
+    fn hide<T: Serialize>(value: &T, rules: &RedactionRules) -> Value {
+        let Value::Object(value) = serde_json::to_value(value).unwrap() else {
+            panic!("Not an object");
+        };
+
+        let mut data = SensitiveData::new(value)
+            .extra_sensitive_keys(EXTRA_SENSITIVE_KEYS)
+            .redaction_rules(Some(rules))
+            .call();
+
+        data.hide();
+        let mut value = data.into_data();
+
+        if let Some(Value::String(body)) = value.get_mut("body") {
+            *body = match serde_json::from_str(body) {
+                Ok(Value::Object(json)) => hide(&json, rules).to_string(),
+                Ok(_) => body.clone(),
+                Err(_) => format!("<non-JSON body, {} bytes>", body.len()),
+            };
+        }
+
+        Value::Object(value)
+    }
+
+    fn assert_hidden<T: Serialize>(value: &T) {
+        for strategy in MASK_STRATEGIES {
+            let rules = rules(strategy);
+            let mut redacted = redact(value, &rules);
+
+            // The body is stringified from a map in another order, so it is compared as JSON
+            if let Some(Value::String(body)) = redacted.get_mut("body") {
+                if let Ok(json) = serde_json::from_str::<Value>(body) {
+                    *body = json.to_string();
+                }
+            }
+
+            assert_eq!(redacted, hide(value, &rules), "{strategy:?}");
+        }
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct User {
+        name: String,
+        password: String,
+        card: Option<u64>,
+        pin: u32,
+        contact: Contact,
+        items: Vec<String>,
+        scores: BTreeMap<u32, String>,
+        settings: HashMap<String, Value>,
+        point: (i32, String),
+        events: Vec<Event>,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Contact {
+        email: String,
+        phone: Option<String>,
+        access_token: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    enum Event {
+        Login { user_token: String, at: u64 },
+        Payment(String, u32),
+        Note(String),
+        Card(String),
+        Logout,
+    }
+
+    fn user() -> User {
+        User {
+            name: "alice".to_string(),
+            password: "hunter22".to_string(),
+            card: Some(4111111111111111),
+            pin: 1234,
+            contact: Contact {
+                email: "alice@example.com".to_string(),
+                phone: None,
+                access_token: Some("token".to_string()),
+            },
+            items: vec!["apple".to_string(), "banana".to_string()],
+            scores: BTreeMap::from([(1, "alice@example.com".to_string()), (2, "ok".to_string())]),
+            settings: HashMap::from([
+                ("theme".to_string(), json!("dark")),
+                ("clientSecret".to_string(), json!({ "nested": true })),
+                ("pin".to_string(), json!([1, 2])),
+            ]),
+            point: (1, "4111 1111 1111 1111".to_string()),
+            events: vec![
+                Event::Login {
+                    user_token: "token".to_string(),
+                    at: 1,
+                },
+                Event::Payment("4111111111111111".to_string(), 2),
+                Event::Note("bob@example.com".to_string()),
+                Event::Card("visa".to_string()),
+                Event::Logout,
+            ],
+        }
+    }
+
+    #[test]
+    fn structs_maps_sequences_and_variants_are_hidden_like_sensitive_data() {
+        assert_hidden(&user());
+    }
+
+    #[test]
+    fn sequences_of_sensitive_values_are_hidden_like_sensitive_data() {
+        let value = json!({
+            "items": [["nested"], { "card": 1 }, null],
+            "list": [["password", "a@example.com"], [{ "password": "x", "other": [1] }]],
+        });
+
+        assert_hidden(&value);
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Request {
+        body: String,
+        is_base64_encoded: bool,
+    }
+
+    #[test]
+    fn json_body_is_hidden_like_sensitive_data() {
+        let body = json!({
+            "user": { "password": "x", "name": "alice" },
+            "items": [1, 2],
+            "card": "visa",
+        });
+
+        let request = Request {
+            body: body.to_string(),
+            is_base64_encoded: false,
+        };
+
+        assert_hidden(&request);
+
+        let redacted = redact(&request, &rules(MaskStrategy::Remove));
+        let body = serde_json::from_str::<Value>(redacted["body"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({ "user": { "name": "alice" }, "items": [null, null] })
+        );
+    }
+
+    #[test]
+    fn non_json_or_base64_body_is_replaced_with_a_placeholder() {
+        for (body, is_base64_encoded) in [
+            ("password=x&client_secret=y".to_string(), false),
+            (
+                STANDARD.encode(json!({ "password": "x" }).to_string()),
+                true,
+            ),
+        ] {
+            let request = Request {
+                body: body.clone(),
+                is_base64_encoded,
+            };
+
+            assert_hidden(&request);
+
+            for strategy in MASK_STRATEGIES {
+                assert_eq!(
+                    redact(&request, &rules(strategy))["body"],
+                    format!("<non-JSON body, {} bytes>", body.len())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn body_other_than_a_string_is_shown_as_is() {
+        let value = json!({ "body": null, "nested": { "body": 1 } });
+
+        assert_eq!(redact(&value, &rules(MaskStrategy::Remove)), value);
+    }
+
+    #[derive(Serialize)]
+    struct Payment {
+        card: Secret<String>,
+        items: Vec<Secret<String>>,
+        note: Secret<String>,
+        body: Secret<String>,
+        contact: Option<Secret<String>>,
+        method: Method,
+        keys: SecretKeys,
+    }
+
+    #[derive(Serialize)]
+    struct Method {
+        number: Secret<String>,
+    }
+
+    struct SecretKeys(Vec<(Secret<String>, u32)>);
+
+    impl Serialize for SecretKeys {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+        }
+    }
+
+    #[test]
+    fn secret_is_masked_before_any_rule_sees_it() {
+        let payment = Payment {
+            card: Secret::new("4111111111111111".to_string()),
+            items: vec![Secret::new("4111111111111111".to_string())],
+            note: Secret::new("alice@example.com".to_string()),
+            body: Secret::new(json!({ "name": "alice" }).to_string()),
+            contact: Some(Secret::new("alice@example.com".to_string())),
+            method: Method {
+                number: Secret::new("4111111111111111".to_string()),
+            },
+            keys: SecretKeys(vec![(Secret::new("4111111111111111".to_string()), 1)]),
+        };
+
+        for strategy in MASK_STRATEGIES {
+            let rules = rules(strategy).key("method");
+            let redacted = redact(&payment, &rules);
+
+            let mut expected = json!({
+                "card": "***",
+                "items": ["***"],
+                "note": "***",
+                "body": "***",
+                "contact": "***",
+                "keys": { "***": 1 },
+            });
+
+            // A struct masked as a whole is masked as its JSON text, where the secret is masked too
+            if let Some(method) = rules.mask_str(strategy, r#"{"number":"***"}"#) {
+                expected["method"] = json!(method);
+            }
+
+            assert_eq!(redacted, expected, "{strategy:?}");
+        }
+    }
+
+    // Serializes its entries with serialize_key and serialize_value rather than serialize_entry
+    struct SplitEntries(Vec<(&'static str, Value)>);
+
+    impl Serialize for SplitEntries {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(self.0.len()))?;
+
+            for (key, value) in &self.0 {
+                map.serialize_key(key)?;
+                map.serialize_value(value)?;
+            }
+
+            map.end()
+        }
+    }
+
+    #[test]
+    fn map_entries_split_into_key_and_value_are_hidden_like_sensitive_data() {
+        let value = SplitEntries(vec![
+            ("name", json!("alice")),
+            ("password", json!("x")),
+            ("card", json!(4111111111111111u64)),
+            ("items", json!(["a", { "password": "x" }])),
+            (
+                "nested",
+                json!({ "refresh_token": "x", "email": "a@example.com" }),
+            ),
+        ]);
+
+        assert_hidden(&value);
+    }
+
+    struct Failing;
+
+    impl Serialize for Failing {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(S::Error::custom("failing"))
+        }
+    }
+
+    #[derive(Serialize)]
+    enum Action {
+        Update {
+            failing: RefCell<Option<Failing>>,
+            note: String,
+        },
+        Move(RefCell<Option<Failing>>, String),
+    }
+
+    #[test]
+    fn path_is_balanced_after_error_within_variant() {
+        let rules = RedactionRules::empty()
+            .mask(MaskStrategy::Replace)
+            .path("/Update/note")
+            .path("/Move/1");
+
+        for (action, expected) in [
+            (
+                Action::Update {
+                    failing: RefCell::new(Some(Failing)),
+                    note: "note".to_string(),
+                },
+                json!({ "Update": { "failing": null, "note": "***" } }),
+            ),
+            (
+                Action::Move(RefCell::new(Some(Failing)), "note".to_string()),
+                json!({ "Move": [null, "***"] }),
+            ),
+        ] {
+            let redacted = Redacted::new(&action).redaction_rules(Some(&rules)).call();
+
+            assert!(serde_json::to_value(&redacted).is_err());
+            assert!(redacted.context.path.borrow().is_empty());
+
+            let (Action::Update { failing, .. } | Action::Move(failing, _)) = &action;
+            *failing.borrow_mut() = None;
+
+            // The path rules only match when nothing is left over from the failed attempt
+            assert_eq!(serde_json::to_value(&redacted).unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use crate::{error_code::CommonErrorCode, CommonError};
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::{self, Display, Formatter},
+    future::Future,
+    sync::Mutex,
+};
+
+// What gets stored for each refresh token, which never includes the token itself. Every token
+// rotated from the same login shares a family, so that the whole family can be revoked at once
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RefreshTokenRecord {
+    pub token_hash: String,
+    pub family_id: String,
+    pub subject: String,
+    pub issued_at: u64,
+    pub expires_at: u64,
+
+    // Set once the token has been exchanged for a new one, after which any use of it is a reuse
+    pub is_used: bool,
+}
+
+// Backed by e.g. a DynamoDB table in services, and by InMemoryRevocationStore in tests. The futures
+// are Send so that the rotator can be used from multi-threaded runtimes and tower services
+pub trait RevocationStore {
+    fn get(
+        &self,
+        token_hash: &str,
+    ) -> impl Future<Output = anyhow::Result<Option<RefreshTokenRecord>>> + Send;
+
+    fn put(&self, record: RefreshTokenRecord) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // Must be atomic, e.g. a conditional update, and returns false when the token was already used
+    fn mark_used(&self, token_hash: &str) -> impl Future<Output = anyhow::Result<bool>> + Send;
+
+    fn revoke_family(&self, family_id: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn is_family_revoked(
+        &self,
+        family_id: &str,
+    ) -> impl Future<Output = anyhow::Result<bool>> + Send;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryRevocationStore {
+    records: Mutex<HashMap<String, RefreshTokenRecord>>,
+    revoked_family_ids: Mutex<HashSet<String>>,
+}
+
+impl RevocationStore for InMemoryRevocationStore {
+    async fn get(&self, token_hash: &str) -> anyhow::Result<Option<RefreshTokenRecord>> {
+        let records = self.records.lock().map_err(|err| anyhow!("{err}"))?;
+        Ok(records.get(token_hash).cloned())
+    }
+
+    async fn put(&self, record: RefreshTokenRecord) -> anyhow::Result<()> {
+        let mut records = self.records.lock().map_err(|err| anyhow!("{err}"))?;
+        records.insert(record.token_hash.to_string(), record);
+        Ok(())
+    }
+
+    async fn mark_used(&self, token_hash: &str) -> anyhow::Result<bool> {
+        let mut records = self.records.lock().map_err(|err| anyhow!("{err}"))?;
+
+        match records.get_mut(token_hash) {
+            Some(record) if !record.is_used => {
+                record.is_used = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> anyhow::Result<()> {
+        let mut revoked_family_ids = self
+            .revoked_family_ids
+            .lock()
+            .map_err(|err| anyhow!("{err}"))?;
+        revoked_family_ids.insert(family_id.to_string());
+        Ok(())
+    }
+
+    async fn is_family_revoked(&self, family_id: &str) -> anyhow::Result<bool> {
+        let revoked_family_ids = self
+            .revoked_family_ids
+            .lock()
+            .map_err(|err| anyhow!("{err}"))?;
+        Ok(revoked_family_ids.contains(family_id))
+    }
+}
+
+// Issues opaque refresh tokens and exchanges each of them for a new one exactly once. Presenting a
+// token which has already been exchanged means it has leaked, so its whole family gets revoked
+#[derive(Debug)]
+pub struct RefreshTokenRotator<S> {
+    store: S,
+
+    // Seconds each token stays valid for
+    ttl: u64,
+}
+
+impl<S: RevocationStore> RefreshTokenRotator<S> {
+    pub fn new(store: S, ttl: u64) -> Self {
+        Self { store, ttl }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    // Starts a new family, e.g. on login, and returns the token to give to the client
+    pub async fn issue(
+        &self,
+        subject: &str,
+    ) -> Result<(String, RefreshTokenRecord), RefreshTokenError> {
+        let family_id = crate::gen_secret_token().byte_count(16usize).call();
+        self.issue_in_family(&family_id, subject).await
+    }
+
+    pub async fn rotate(
+        &self,
+        token: &str,
+    ) -> Result<(String, RefreshTokenRecord), RefreshTokenError> {
+        let record = self.verify(token).await?;
+
+        if !self
+            .store
+            .mark_used(&record.token_hash)
+            .await
+            .map_err(RefreshTokenError::Store)?
+        {
+            // Lost a race against another exchange of the same token, which is a reuse all the same
+            self.revoke_family(&record.family_id).await?;
+            return Err(RefreshTokenError::Reused);
+        }
+
+        self.issue_in_family(&record.family_id, &record.subject)
+            .await
+    }
+
+    // Checks the token without using it up
+    pub async fn verify(&self, token: &str) -> Result<RefreshTokenRecord, RefreshTokenError> {
+        let record = self
+            .store
+            .get(&hash_token(token))
+            .await
+            .map_err(RefreshTokenError::Store)?
+            .ok_or(RefreshTokenError::Invalid)?;
+
+        if self
+            .store
+            .is_family_revoked(&record.family_id)
+            .await
+            .map_err(RefreshTokenError::Store)?
+        {
+            return Err(RefreshTokenError::Revoked);
+        }
+
+        if record.is_used {
+            self.revoke_family(&record.family_id).await?;
+            return Err(RefreshTokenError::Reused);
+        }
+
+        if record.expires_at <= now()? {
+            return Err(RefreshTokenError::Expired);
+        }
+
+        Ok(record)
+    }
+
+    // Revokes the token together with every token rotated from it, e.g. on logout
+    pub async fn revoke(&self, token: &str) -> Result<(), RefreshTokenError> {
+        let Some(record) = self
+            .store
+            .get(&hash_token(token))
+            .await
+            .map_err(RefreshTokenError::Store)?
+        else {
+            return Err(RefreshTokenError::Invalid);
+        };
+
+        self.revoke_family(&record.family_id).await
+    }
+
+    pub async fn revoke_family(&self, family_id: &str) -> Result<(), RefreshTokenError> {
+        self.store
+            .revoke_family(family_id)
+            .await
+            .map_err(RefreshTokenError::Store)
+    }
+
+    async fn issue_in_family(
+        &self,
+        family_id: &str,
+        subject: &str,
+    ) -> Result<(String, RefreshTokenRecord), RefreshTokenError> {
+        let token = crate::gen_secret_token().call();
+        let now = now()?;
+
+        let record = RefreshTokenRecord {
+            token_hash: hash_token(&token),
+            family_id: family_id.to_string(),
+            subject: subject.to_string(),
+            issued_at: now,
+            expires_at: now.saturating_add(self.ttl),
+            is_used: false,
+        };
+
+        self.store
+            .put(record.clone())
+            .await
+            .map_err(RefreshTokenError::Store)?;
+
+        Ok((token, record))
+    }
+}
+
+// Refresh tokens are random enough that a fast unsalted hash is safe, which also lets the hash
+// be used as the lookup key
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Debug)]
+pub enum RefreshTokenError {
+    Store(anyhow::Error),
+    Clock(anyhow::Error),
+    Invalid,
+    Expired,
+    Revoked,
+    Reused,
+}
+
+impl Display for RefreshTokenError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Store(err) => write!(fmt, "Failed to access the revocation store: {err}"),
+            Self::Clock(err) => write!(fmt, "Failed to get the current time: {err}"),
+            Self::Invalid => write!(fmt, "Refresh token is invalid"),
+            Self::Expired => write!(fmt, "Refresh token has expired"),
+            Self::Revoked => write!(fmt, "Refresh token has been revoked"),
+            Self::Reused => write!(fmt, "Refresh token has already been used"),
+        }
+    }
+}
+
+impl Error for RefreshTokenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Store(err) | Self::Clock(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<RefreshTokenError> for CommonError {
+    fn from(err: RefreshTokenError) -> Self {
+        match err {
+            RefreshTokenError::Store(_) | RefreshTokenError::Clock(_) => {
+                CommonErrorCode::InternalServerError.into()
+            }
+            _ => CommonError {
+                code: CommonErrorCode::Unauthorized.into(),
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+fn now() -> Result<u64, RefreshTokenError> {
+    crate::get_current_timestamp()
+        .call()
+        .map_err(RefreshTokenError::Clock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Builder;
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        Builder::new_current_thread().build().unwrap().block_on(fut)
+    }
+
+    fn assert_send<T: Send>(value: T) -> T {
+        value
+    }
+
+    fn rotator() -> RefreshTokenRotator<InMemoryRevocationStore> {
+        RefreshTokenRotator::new(InMemoryRevocationStore::default(), 60)
+    }
+
+    #[test]
+    fn rotation_replaces_the_token_within_its_family() {
+        let rotator = rotator();
+
+        block_on(async {
+            let (token, record) = assert_send(rotator.issue("user")).await.unwrap();
+            let (next_token, next_record) = assert_send(rotator.rotate(&token)).await.unwrap();
+
+            assert_ne!(next_token, token);
+            assert_eq!(next_record.token_hash, hash_token(&next_token));
+            assert_eq!(next_record.family_id, record.family_id);
+            assert_eq!(next_record.subject, "user");
+            assert!(
+                rotator
+                    .store()
+                    .get(&record.token_hash)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .is_used
+            );
+            assert_eq!(rotator.verify(&next_token).await.unwrap(), next_record);
+        });
+    }
+
+    #[test]
+    fn reuse_revokes_the_family() {
+        let rotator = rotator();
+
+        block_on(async {
+            let (token, record) = rotator.issue("user").await.unwrap();
+            let (next_token, _) = rotator.rotate(&token).await.unwrap();
+            let (other_token, _) = rotator.issue("user").await.unwrap();
+
+            assert!(matches!(
+                rotator.rotate(&token).await,
+                Err(RefreshTokenError::Reused)
+            ));
+            assert!(rotator
+                .store()
+                .is_family_revoked(&record.family_id)
+                .await
+                .unwrap());
+            assert!(matches!(
+                rotator.rotate(&next_token).await,
+                Err(RefreshTokenError::Revoked)
+            ));
+
+            // Other logins of the same subject are left alone
+            assert!(rotator.rotate(&other_token).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn revoke_family_revokes_every_token_in_it() {
+        let rotator = rotator();
+
+        block_on(async {
+            let (token, record) = rotator.issue("user").await.unwrap();
+            let (next_token, _) = rotator.rotate(&token).await.unwrap();
+
+            rotator.revoke_family(&record.family_id).await.unwrap();
+
+            for token in [&token, &next_token] {
+                assert!(matches!(
+                    rotator.verify(token).await,
+                    Err(RefreshTokenError::Revoked)
+                ));
+            }
+        });
+    }
+
+    #[test]
+    fn unknown_or_expired_token_is_rejected() {
+        let rotator = RefreshTokenRotator::new(InMemoryRevocationStore::default(), 0);
+
+        block_on(async {
+            let (token, _) = rotator.issue("user").await.unwrap();
+
+            assert!(matches!(
+                rotator.rotate(&token).await,
+                Err(RefreshTokenError::Expired)
+            ));
+            assert!(matches!(
+                rotator.rotate("unknown").await,
+                Err(RefreshTokenError::Invalid)
+            ));
+        });
+    }
+}
@@ -0,0 +1,399 @@
+use crate::{
+    api_request::ApiRequest, common_serde::LoadError, request_de::RequestField, UploadedFile,
+};
+use aws_lambda_events::http::header::CONTENT_TYPE;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde_json::{Map, Value};
+use std::{borrow::Cow, collections::HashMap};
+
+pub(crate) fn parse(event: &impl ApiRequest) -> Result<Vec<(String, RequestField)>, LoadError> {
+    let Some(body) = event.body().filter(|body| !body.is_empty()) else {
+        return Ok(vec![]);
+    };
+
+    let body = if event.is_base64_encoded() {
+        Cow::Owned(STANDARD.decode(body).map_err(LoadError::Base64)?)
+    } else {
+        Cow::Borrowed(body.as_bytes())
+    };
+
+    let content_type = event
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|content_type| content_type.to_str().unwrap_or_default())
+        .unwrap_or("application/json");
+
+    let (media_type, params) = parse_header_value(content_type);
+
+    match media_type.to_ascii_lowercase().as_str() {
+        "application/json" => parse_json(&body),
+        media_type if media_type.starts_with("application/") && media_type.ends_with("+json") => {
+            parse_json(&body)
+        }
+        "application/x-www-form-urlencoded" => Ok(parse_form_urlencoded(&body)),
+        "multipart/form-data" => {
+            let boundary = params
+                .get("boundary")
+                .ok_or_else(|| LoadError::Multipart("Missing boundary".to_string()))?;
+
+            parse_multipart(&body, boundary)
+        }
+        _ => Err(LoadError::UnsupportedMediaType(media_type.to_string())),
+    }
+}
+
+fn parse_json(body: &[u8]) -> Result<Vec<(String, RequestField)>, LoadError> {
+    let body = serde_json::from_slice::<Map<String, Value>>(body).map_err(LoadError::Body)?;
+
+    Ok(body
+        .into_iter()
+        .map(|(k, v)| (k, RequestField::Body(v)))
+        .collect())
+}
+
+fn parse_form_urlencoded(body: &[u8]) -> Vec<(String, RequestField)> {
+    let mut fields = HashMap::<String, Vec<String>>::new();
+
+    for (k, v) in form_urlencoded::parse(body) {
+        fields
+            .entry(k.into_owned())
+            .or_default()
+            .push(v.into_owned());
+    }
+
+    fields
+        .into_iter()
+        .map(|(k, v)| (k, RequestField::Param(v)))
+        .collect()
+}
+
+fn parse_multipart(body: &[u8], boundary: &str) -> Result<Vec<(String, RequestField)>, LoadError> {
+    let delimiter = [b"--", boundary.as_bytes()].concat();
+    let part_delimiter = [b"\r\n--", boundary.as_bytes()].concat();
+
+    let Some(start) = find(body, &delimiter) else {
+        return Err(LoadError::Multipart("Missing boundary".to_string()));
+    };
+
+    let mut rest = &body[start + delimiter.len()..];
+    let mut params = HashMap::<String, Vec<String>>::new();
+    let mut files = HashMap::<String, Vec<UploadedFile>>::new();
+
+    // Each iteration starts right after a delimiter, which is followed by "--" for the last one
+    while !rest.starts_with(b"--") {
+        let part = rest
+            .strip_prefix(b"\r\n")
+            .ok_or_else(|| LoadError::Multipart("Malformed boundary".to_string()))?;
+
+        let headers_end = find(part, b"\r\n\r\n")
+            .ok_or_else(|| LoadError::Multipart("Malformed part headers".to_string()))?;
+
+        let content_start = headers_end + 4;
+
+        let content_end = find(&part[content_start..], &part_delimiter)
+            .map(|content_len| content_start + content_len)
+            .ok_or_else(|| LoadError::Multipart("Missing closing boundary".to_string()))?;
+
+        let headers = String::from_utf8_lossy(&part[..headers_end]);
+        let mut name = None;
+        let mut file_name = None;
+        let mut content_type = None;
+
+        for header in headers.split("\r\n") {
+            let Some((k, v)) = header.split_once(':') else {
+                continue;
+            };
+
+            if k.trim().eq_ignore_ascii_case("content-disposition") {
+                let (_, mut disposition_params) = parse_header_value(v.trim());
+                name = disposition_params.remove("name");
+                file_name = disposition_params.remove("filename");
+            } else if k.trim().eq_ignore_ascii_case("content-type") {
+                content_type = Some(v.trim().to_string());
+            }
+        }
+
+        let name = name.ok_or_else(|| LoadError::Multipart("Missing part name".to_string()))?;
+        let content = &part[content_start..content_end];
+
+        // Only parts sent as a file are kept as bytes, the others are plain form fields
+        if file_name.is_some() {
+            files.entry(name).or_default().push(UploadedFile {
+                file_name,
+                content_type,
+                content: content.to_vec(),
+            });
+        } else {
+            let content = String::from_utf8(content.to_vec())
+                .map_err(|_| LoadError::Multipart(format!("Part {name} is not UTF-8")))?;
+
+            params.entry(name).or_default().push(content);
+        }
+
+        rest = &part[content_end + part_delimiter.len()..];
+    }
+
+    Ok(params
+        .into_iter()
+        .map(|(k, v)| (k, RequestField::Param(v)))
+        .chain(files.into_iter().map(|(k, v)| (k, RequestField::Files(v))))
+        .collect())
+}
+
+// Splits a header value like `multipart/form-data; boundary="abc"` into its main value and params
+fn parse_header_value(value: &str) -> (&str, HashMap<String, String>) {
+    let mut parts = value.split(';');
+    let main_value = parts.next().unwrap_or_default().trim();
+
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(k, v)| {
+            let v = v.trim();
+            let v = v
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(v);
+
+            (k.trim().to_ascii_lowercase(), v.to_string())
+        })
+        .collect();
+
+    (main_value, params)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common_serde::Request, error_code::CommonErrorCode};
+    use aws_lambda_events::{apigw::ApiGatewayProxyRequest, http::HeaderValue};
+    use serde::Deserialize;
+    use serde_json::json;
+    use validator::Validate;
+
+    const BOUNDARY: &str = "----boundary";
+
+    fn event(
+        content_type: Option<&str>,
+        body: &str,
+        is_base64_encoded: bool,
+    ) -> ApiGatewayProxyRequest {
+        let mut event = ApiGatewayProxyRequest {
+            body: Some(body.to_string()),
+            is_base64_encoded,
+            ..Default::default()
+        };
+
+        if let Some(content_type) = content_type {
+            event
+                .headers
+                .insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        }
+
+        event
+    }
+
+    fn parse_fields(event: &ApiGatewayProxyRequest) -> HashMap<String, RequestField> {
+        parse(event).unwrap().into_iter().collect()
+    }
+
+    fn multipart_body() -> String {
+        [
+            "preamble",
+            "------boundary",
+            "Content-Disposition: form-data; name=\"name\"",
+            "",
+            "Alice",
+            "------boundary",
+            "Content-Disposition: form-data; name=\"tags\"",
+            "",
+            "a",
+            "------boundary",
+            "Content-Disposition: form-data; name=\"tags\"",
+            "",
+            "b",
+            "------boundary",
+            "Content-Disposition: form-data; name=\"avatar\"; filename=\"avatar.png\"",
+            "Content-Type: image/png",
+            "",
+            "\u{89}PNG\r\n",
+            "------boundary--",
+            "",
+        ]
+        .join("\r\n")
+    }
+
+    #[test]
+    fn json_body_is_parsed_by_default() {
+        let fields = parse_fields(&event(None, r#"{"name":"Alice","age":3}"#, false));
+
+        assert_eq!(fields["name"], RequestField::Body(json!("Alice")));
+        assert_eq!(fields["age"], RequestField::Body(json!(3)));
+
+        let fields = parse_fields(&event(
+            Some("application/merge-patch+json; charset=utf-8"),
+            r#"{"name":"Alice"}"#,
+            false,
+        ));
+
+        assert_eq!(fields["name"], RequestField::Body(json!("Alice")));
+    }
+
+    #[test]
+    fn base64_body_is_decoded() {
+        let body = STANDARD.encode(r#"{"name":"Alice"}"#);
+        let fields = parse_fields(&event(Some("application/json"), &body, true));
+
+        assert_eq!(fields["name"], RequestField::Body(json!("Alice")));
+
+        let body = STANDARD.encode("name=Alice");
+        let fields = parse_fields(&event(
+            Some("application/x-www-form-urlencoded"),
+            &body,
+            true,
+        ));
+
+        assert_eq!(
+            fields["name"],
+            RequestField::Param(vec!["Alice".to_string()])
+        );
+
+        assert!(matches!(
+            parse(&event(None, "not base64!", true)),
+            Err(LoadError::Base64(_))
+        ));
+    }
+
+    #[test]
+    fn form_urlencoded_keeps_repeated_keys() {
+        let fields = parse_fields(&event(
+            Some("application/x-www-form-urlencoded"),
+            "tag=a&name=Alice+B%26C&tag=b&empty=",
+            false,
+        ));
+
+        assert_eq!(
+            fields["tag"],
+            RequestField::Param(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            fields["name"],
+            RequestField::Param(vec!["Alice B&C".to_string()])
+        );
+        assert_eq!(fields["empty"], RequestField::Param(vec!["".to_string()]));
+    }
+
+    #[test]
+    fn multipart_keeps_files_and_text_fields() {
+        let content_type = format!("multipart/form-data; boundary=\"{BOUNDARY}\"");
+        let fields = parse_fields(&event(Some(&content_type), &multipart_body(), false));
+
+        assert_eq!(
+            fields["name"],
+            RequestField::Param(vec!["Alice".to_string()])
+        );
+        assert_eq!(
+            fields["tags"],
+            RequestField::Param(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            fields["avatar"],
+            RequestField::Files(vec![UploadedFile {
+                file_name: Some("avatar.png".to_string()),
+                content_type: Some("image/png".to_string()),
+                content: "\u{89}PNG\r\n".as_bytes().to_vec(),
+            }])
+        );
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct UploadAvatarRequest {
+        name: String,
+        tags: Vec<String>,
+        avatar: UploadedFile,
+    }
+
+    impl Request for UploadAvatarRequest {}
+
+    #[test]
+    fn multipart_loads_into_uploaded_file() {
+        let content_type = format!("multipart/form-data; boundary={BOUNDARY}");
+        let req = UploadAvatarRequest::load(&event(Some(&content_type), &multipart_body(), false))
+            .unwrap();
+
+        assert_eq!(req.name, "Alice");
+        assert_eq!(req.tags, ["a", "b"]);
+        assert_eq!(req.avatar.file_name.as_deref(), Some("avatar.png"));
+        assert_eq!(req.avatar.content_type.as_deref(), Some("image/png"));
+        assert_eq!(req.avatar.content, "\u{89}PNG\r\n".as_bytes());
+    }
+
+    #[test]
+    fn multipart_without_valid_boundary_is_rejected() {
+        for (content_type, expected) in [
+            ("multipart/form-data", "Missing boundary"),
+            ("multipart/form-data; boundary=other", "Missing boundary"),
+        ] {
+            let err = parse(&event(Some(content_type), &multipart_body(), false)).unwrap_err();
+
+            assert!(
+                matches!(&err, LoadError::Multipart(message) if message == expected),
+                "{err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_part_is_rejected() {
+        let content_type = format!("multipart/form-data; boundary={BOUNDARY}");
+
+        // Sent as base64 so that the part which is not UTF-8 can be sent at all
+        for (body, expected) in [
+            (&b"------boundaryjunk\r\n"[..], "Malformed boundary"),
+            (
+                b"------boundary\r\nContent-Disposition: form-data; name=a\r\nb\r\n------boundary--",
+                "Malformed part headers",
+            ),
+            (
+                b"------boundary\r\nContent-Disposition: form-data; name=a\r\n\r\nb",
+                "Missing closing boundary",
+            ),
+            (
+                b"------boundary\r\nContent-Disposition: form-data\r\n\r\nb\r\n------boundary--",
+                "Missing part name",
+            ),
+            (
+                b"------boundary\r\nContent-Disposition: form-data; name=a\r\n\r\n\xff\r\n------boundary--",
+                "Part a is not UTF-8",
+            ),
+        ] {
+            let body = STANDARD.encode(body);
+            let err = parse(&event(Some(&content_type), &body, true)).unwrap_err();
+
+            assert!(
+                matches!(&err, LoadError::Multipart(message) if message == expected),
+                "{err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_content_type_is_unsupported_media_type() {
+        let err = parse(&event(Some("text/plain; charset=utf-8"), "hi", false)).unwrap_err();
+
+        assert!(
+            matches!(&err, LoadError::UnsupportedMediaType(media_type) if media_type == "text/plain"),
+            "{err:?}"
+        );
+
+        let resp = err.into_api_resp("request id");
+
+        assert_eq!(resp.code, CommonErrorCode::UnsupportedMediaType.into());
+        assert_eq!(resp.payload, json!({ "content_type": "text/plain" }));
+    }
+}
@@ -0,0 +1,460 @@
+use crate::UploadedFile;
+use serde::{
+    de::{
+        value::{MapDeserializer, SeqDeserializer},
+        Error as _, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+use serde_json::{json, Error, Value};
+use std::{collections::HashMap, str::FromStr};
+
+// A request field either keeps the JSON type it was sent with, or is a path, query or form
+// parameter which is always a string and gets coerced into the type the request struct asks for,
+// or is an uploaded file
+#[derive(Debug, PartialEq)]
+pub(crate) enum RequestField {
+    Body(Value),
+    Param(Vec<String>),
+    Files(Vec<UploadedFile>),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct RequestDeserializer(pub(crate) HashMap<String, RequestField>);
+
+impl<'de> Deserializer<'de> for RequestDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let fields = self
+            .0
+            .into_iter()
+            .map(|(name, field)| (name.clone(), NamedField { name, field }));
+
+        visitor.visit_map(MapDeserializer::new(fields))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+// Prefixes the errors of a field with its name, as serde does not say which field failed
+struct NamedField {
+    name: String,
+    field: RequestField,
+}
+
+impl<'de> IntoDeserializer<'de, Error> for NamedField {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+macro_rules! deserialize_named {
+    ($($method:ident($($arg:ident: $ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                $($arg: $ty,)*
+                visitor: V,
+            ) -> Result<V::Value, Self::Error> {
+                let Self { name: field_name, field } = self;
+
+                field
+                    .$method($($arg,)* visitor)
+                    .map_err(|err| Error::custom(format!("{field_name}: {err}")))
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for NamedField {
+    type Error = Error;
+
+    deserialize_named! {
+        deserialize_any(),
+        deserialize_bool(),
+        deserialize_i8(),
+        deserialize_i16(),
+        deserialize_i32(),
+        deserialize_i64(),
+        deserialize_i128(),
+        deserialize_u8(),
+        deserialize_u16(),
+        deserialize_u32(),
+        deserialize_u64(),
+        deserialize_u128(),
+        deserialize_f32(),
+        deserialize_f64(),
+        deserialize_char(),
+        deserialize_str(),
+        deserialize_string(),
+        deserialize_bytes(),
+        deserialize_byte_buf(),
+        deserialize_option(),
+        deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str),
+        deserialize_seq(),
+        deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize),
+        deserialize_map(),
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(),
+        deserialize_ignored_any(),
+    }
+}
+
+// Finds the field names of a struct from its Deserialize impl without deserializing anything. None
+// when the struct is deserialized as a map, e.g. with #[serde(flatten)]
+pub(crate) fn get_field_names<'de, T: Deserialize<'de>>() -> Option<&'static [&'static str]> {
+    let mut field_names = None;
+    let _ = T::deserialize(FieldNamesDeserializer(&mut field_names));
+    field_names
+}
+
+struct FieldNamesDeserializer<'a>(&'a mut Option<&'static [&'static str]>);
+
+impl<'de> Deserializer<'de> for FieldNamesDeserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = Some(fields);
+        Err(Error::custom("only the field names are read"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for RequestField {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl RequestField {
+    // A repeated parameter used as a single value takes the last one, like a plain map insert would
+    fn into_last_param(self) -> Result<String, Error> {
+        match self {
+            Self::Param(mut values) => values
+                .pop()
+                .ok_or_else(|| Error::custom("parameter has no value")),
+            Self::Body(_) | Self::Files(_) | Self::Bytes(_) => {
+                Err(Error::custom("field is not a parameter"))
+            }
+        }
+    }
+
+    fn parse<T: FromStr>(self, expected: &str) -> Result<T, Error> {
+        let value = self.into_last_param()?;
+
+        value
+            .parse()
+            .map_err(|_| Error::custom(format!("invalid {expected}: {value:?}")))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self {
+                    Self::Body(value) => value.$method(visitor),
+                    param => visitor.$visit(param.parse::<$ty>(stringify!($ty))?),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for RequestField {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Self::Body(value) => value.deserialize_any(visitor),
+            Self::Files(mut files) => {
+                let file = files
+                    .pop()
+                    .ok_or_else(|| Error::custom("file field has no file"))?;
+
+                let file = [
+                    ("file_name", Self::Body(json!(file.file_name))),
+                    ("content_type", Self::Body(json!(file.content_type))),
+                    ("content", Self::Bytes(file.content)),
+                ];
+
+                visitor.visit_map(MapDeserializer::new(file.into_iter()))
+            }
+            Self::Bytes(bytes) => visitor.visit_byte_buf(bytes),
+            param => visitor.visit_string(param.into_last_param()?),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool(bool),
+        deserialize_i8 => visit_i8(i8),
+        deserialize_i16 => visit_i16(i16),
+        deserialize_i32 => visit_i32(i32),
+        deserialize_i64 => visit_i64(i64),
+        deserialize_i128 => visit_i128(i128),
+        deserialize_u8 => visit_u8(u8),
+        deserialize_u16 => visit_u16(u16),
+        deserialize_u32 => visit_u32(u32),
+        deserialize_u64 => visit_u64(u64),
+        deserialize_u128 => visit_u128(u128),
+        deserialize_f32 => visit_f32(f32),
+        deserialize_f64 => visit_f64(f64),
+        deserialize_char => visit_char(char),
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Self::Body(value) => value.deserialize_option(visitor),
+            param => visitor.visit_some(param),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self {
+            Self::Body(value) => value.deserialize_newtype_struct(name, visitor),
+            param => visitor.visit_newtype_struct(param),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Self::Body(value) => value.deserialize_seq(visitor),
+            Self::Param(values) => {
+                let values = values.into_iter().map(|value| Self::Param(vec![value]));
+                let mut seq = SeqDeserializer::new(values);
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            }
+            Self::Files(files) => {
+                let files = files.into_iter().map(|file| Self::Files(vec![file]));
+                let mut seq = SeqDeserializer::new(files);
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            }
+            Self::Bytes(bytes) => {
+                let mut seq = SeqDeserializer::new(bytes.into_iter());
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            }
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self {
+            Self::Body(value) => value.deserialize_enum(name, variants, visitor),
+            param => visitor.visit_enum(param.into_last_param()?.into_deserializer()),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_serde::{LoadError, Request};
+    use aws_lambda_events::apigw::ApiGatewayProxyRequest;
+    use std::collections::HashMap;
+    use validator::Validate;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Sort {
+        Newest,
+        Oldest,
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct ListRequest {
+        user_id: u64,
+        page: u32,
+        offset: i16,
+        ratio: f64,
+        active: bool,
+        sort: Sort,
+        cursor: Option<String>,
+        limit: Option<u8>,
+        tags: Vec<String>,
+        ids: Vec<u32>,
+    }
+
+    impl Request for ListRequest {}
+
+    fn event(path_parameters: &[(&str, &str)], query: &[(&str, &str)]) -> ApiGatewayProxyRequest {
+        let mut query_string_parameters = HashMap::<String, Vec<String>>::new();
+
+        for &(k, v) in query {
+            query_string_parameters
+                .entry(k.to_string())
+                .or_default()
+                .push(v.to_string());
+        }
+
+        ApiGatewayProxyRequest {
+            path_parameters: path_parameters
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            multi_value_query_string_parameters: query_string_parameters.into(),
+            ..Default::default()
+        }
+    }
+
+    fn query<'a>(overrides: &[(&'a str, &'a str)]) -> Vec<(&'a str, &'a str)> {
+        let mut query = vec![
+            ("page", "2"),
+            ("offset", "-5"),
+            ("ratio", "0.5"),
+            ("active", "true"),
+            ("sort", "oldest"),
+            ("tags", "a"),
+            ("ids", "1"),
+        ];
+
+        query.retain(|(k, _)| !overrides.iter().any(|(override_k, _)| override_k == k));
+        query.extend_from_slice(overrides);
+        query
+    }
+
+    #[test]
+    fn params_are_coerced_into_the_field_types() {
+        let req = ListRequest::load(&event(
+            &[("user_id", "42")],
+            &query(&[("limit", "10"), ("tags", "b"), ("ids", "2")]),
+        ))
+        .unwrap();
+
+        assert_eq!(req.user_id, 42);
+        assert_eq!(req.page, 2);
+        assert_eq!(req.offset, -5);
+        assert_eq!(req.ratio, 0.5);
+        assert!(req.active);
+        assert_eq!(req.sort, Sort::Oldest);
+        assert_eq!(req.cursor, None);
+        assert_eq!(req.limit, Some(10));
+    }
+
+    #[test]
+    fn repeated_query_keys_are_loaded_into_vec() {
+        let req = ListRequest::load(&event(
+            &[("user_id", "42")],
+            &[
+                ("page", "1"),
+                ("offset", "0"),
+                ("ratio", "1"),
+                ("active", "false"),
+                ("sort", "newest"),
+                ("cursor", "abc"),
+                ("tags", "a"),
+                ("tags", "b"),
+                ("ids", "1"),
+                ("ids", "2"),
+                ("ids", "3"),
+            ],
+        ))
+        .unwrap();
+
+        assert!(!req.active);
+        assert_eq!(req.sort, Sort::Newest);
+        assert_eq!(req.cursor.as_deref(), Some("abc"));
+        assert_eq!(req.tags, ["a", "b"]);
+        assert_eq!(req.ids, [1, 2, 3]);
+
+        // A repeated key used as a single value takes the last one
+        let req = ListRequest::load(&event(
+            &[("user_id", "42")],
+            &query(&[("page", "3"), ("page", "4")]),
+        ))
+        .unwrap();
+
+        assert_eq!(req.page, 4);
+    }
+
+    #[test]
+    fn bad_param_names_the_field() {
+        for (path_parameters, query, expected) in [
+            (
+                [("user_id", "x")],
+                query(&[]),
+                r#"user_id: invalid u64: "x""#,
+            ),
+            (
+                [("user_id", "1")],
+                query(&[("page", "-1")]),
+                r#"page: invalid u32: "-1""#,
+            ),
+            (
+                [("user_id", "1")],
+                query(&[("active", "yes")]),
+                r#"active: invalid bool: "yes""#,
+            ),
+            (
+                [("user_id", "1")],
+                query(&[("limit", "256")]),
+                r#"limit: invalid u8: "256""#,
+            ),
+            (
+                [("user_id", "1")],
+                query(&[("ids", "1"), ("ids", "a")]),
+                r#"ids: invalid u32: "a""#,
+            ),
+        ] {
+            let err = ListRequest::load(&event(&path_parameters, &query)).unwrap_err();
+
+            let LoadError::Deserialize(err) = err else {
+                panic!("{err:?} is not a deserialize error");
+            };
+
+            assert_eq!(err.to_string(), expected);
+        }
+
+        let err = ListRequest::load(&event(&[("user_id", "1")], &query(&[("sort", "top")])))
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("sort: unknown variant `top`"), "{err}");
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{self, Debug, Display, Formatter};
+use validator::ValidateLength;
+
+pub(crate) const NAME: &str = "$common::Secret";
+
+// Holds a value which is never printed or logged, e.g. Secret<TrimmedString> for passwords. The
+// value can only be read through expose, which makes every use of it stand out
+#[derive(Clone, Copy, Default)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub const fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub const fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "Secret(***)")
+    }
+}
+
+impl<T> Display for Secret<T> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "***")
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self)
+    }
+}
+
+// Serialized as the value itself, e.g. to be forwarded to another service, other than by Redacted
+// which tells secrets apart by this name and masks them wherever they are
+impl<T: Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(NAME, &self.0)
+    }
+}
+
+// For #[validate(length(...))] on secret fields
+impl<T: ValidateLength<u64>> ValidateLength<u64> for Secret<T> {
+    fn length(&self) -> Option<u64> {
+        self.0.length()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Redacted, TrimmedString};
+    use serde_json::json;
+
+    #[derive(Deserialize, Serialize)]
+    struct SignInRequest {
+        username: String,
+        answer: Secret<TrimmedString>,
+        answers: Vec<Secret<String>>,
+    }
+
+    fn request() -> SignInRequest {
+        serde_json::from_value(json!({
+            "username": "alice",
+            "answer": " rex ",
+            "answers": ["a", "b"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn secret_is_never_printed() {
+        let secret = Secret::new("rex");
+
+        assert_eq!(format!("{secret:?}"), "Secret(***)");
+        assert_eq!(secret.to_string(), "***");
+        assert_eq!(*secret.expose(), "rex");
+    }
+
+    #[test]
+    fn secret_is_serialized_as_its_value() {
+        let request = request();
+
+        assert_eq!(request.answer.expose().as_str(), "rex");
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({ "username": "alice", "answer": "rex", "answers": ["a", "b"] })
+        );
+    }
+
+    #[test]
+    fn secret_is_masked_by_redacted() {
+        let request = request();
+
+        assert_eq!(
+            serde_json::to_value(Redacted::new(&request).call()).unwrap(),
+            json!({ "username": "alice", "answer": "***", "answers": ["***", "***"] })
+        );
+        assert_eq!(
+            serde_json::to_value(Redacted::new(&Secret::new(1)).call()).unwrap(),
+            json!("***")
+        );
+    }
+}
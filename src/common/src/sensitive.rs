@@ -0,0 +1,127 @@
+use crate::Redacted;
+use serde::Serialize;
+
+// Meant to be derived with #[derive(Sensitive)], which marks fields with #[sensitive]
+pub trait Sensitive {
+    // The serialized names of the fields marked with #[sensitive]
+    const SENSITIVE_KEYS: &'static [&'static str];
+
+    // To be logged with the marked fields removed on top of the redaction rules, e.g.
+    // info!(request = serde_json::to_string(&request.redacted())?), while the entries of the same
+    // name nested within the fields are kept
+    fn redacted(&self) -> Redacted<'_, Self>
+    where
+        Self: Serialize + Sized,
+    {
+        Redacted::new(self)
+            .sensitive_fields(Self::SENSITIVE_KEYS)
+            .call()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sensitive;
+    use serde_json::json;
+
+    #[derive(Serialize, Sensitive)]
+    #[serde(rename_all = "camelCase")]
+    struct SignUpRequest {
+        user_name: String,
+
+        #[sensitive]
+        security_answer: String,
+
+        #[sensitive]
+        #[serde(rename = "pin_code")]
+        pin: u32,
+    }
+
+    #[derive(Serialize, Sensitive)]
+    struct Pair(String, #[sensitive] String);
+
+    #[derive(Serialize, Sensitive)]
+    struct Grid(Vec<Vec<u32>>, #[sensitive] String);
+
+    #[derive(Serialize, Sensitive)]
+    struct Challenge {
+        #[sensitive]
+        answer: String,
+
+        hint: Hint,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Hint {
+        answer: String,
+    }
+
+    fn request() -> SignUpRequest {
+        SignUpRequest {
+            user_name: "alice".to_string(),
+            security_answer: "rex".to_string(),
+            pin: 1234,
+        }
+    }
+
+    #[test]
+    fn sensitive_keys_are_the_serialized_names() {
+        assert_eq!(
+            SignUpRequest::SENSITIVE_KEYS,
+            ["securityAnswer", "pin_code"]
+        );
+        assert_eq!(Pair::SENSITIVE_KEYS, ["1"]);
+    }
+
+    #[test]
+    fn debug_and_display_mask_the_marked_fields() {
+        let request = request();
+        let expected = r#"SignUpRequest { user_name: "alice", security_answer: ***, pin: *** }"#;
+
+        assert_eq!(format!("{request:?}"), expected);
+        assert_eq!(request.to_string(), expected);
+
+        let pair = Pair("alice".to_string(), "rex".to_string());
+        assert_eq!(format!("{pair:?}"), r#"Pair("alice", ***)"#);
+        assert_eq!(pair.to_string(), r#"Pair("alice", ***)"#);
+    }
+
+    #[test]
+    fn redacted_removes_the_marked_fields() {
+        let request = request();
+
+        assert_eq!(
+            serde_json::to_value(request.redacted()).unwrap(),
+            json!({ "userName": "alice" })
+        );
+
+        let pair = Pair("alice".to_string(), "rex".to_string());
+        assert_eq!(
+            serde_json::to_value(pair.redacted()).unwrap(),
+            json!(["alice", null])
+        );
+    }
+
+    #[test]
+    fn redacted_keeps_nested_entries_of_the_same_name() {
+        let grid = Grid(vec![vec![1, 2], vec![3, 4]], "rex".to_string());
+
+        assert_eq!(
+            serde_json::to_value(grid.redacted()).unwrap(),
+            json!([[[1, 2], [3, 4]], null])
+        );
+
+        let challenge = Challenge {
+            answer: "rex".to_string(),
+            hint: Hint {
+                answer: "a dog".to_string(),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(challenge.redacted()).unwrap(),
+            json!({ "hint": { "answer": "a dog" } })
+        );
+    }
+}
@@ -1,25 +1,20 @@
-use crate::constants;
+use crate::{
+    common_enums::MaskStrategy,
+    redaction::{self, RedactionRules},
+};
 use optarg2chain::optarg_impl;
-use serde_json::{json, Map, Value};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::mem;
 
 #[derive(Debug, PartialEq)]
 pub struct SensitiveData<'a> {
+    // Mirrors the structure of shown_value, holding only the hidden entries and their parents,
+    // while shown_value holds the masked values of the hidden entries if they are not removed
     hidden_value: Map<String, Value>,
+
     shown_value: Map<String, Value>,
     extra_sensitive_keys: &'a [&'a str],
-}
-
-#[derive(Debug, PartialEq)]
-struct SensitiveDataMut<'a> {
-    hidden_value: &'a mut Map<String, Value>,
-    shown_value: &'a mut Map<String, Value>,
-}
-
-#[derive(Debug, PartialEq)]
-struct OptionalSensitiveDataMut<'a> {
-    hidden_value: Option<&'a mut Map<String, Value>>,
-    shown_value: Option<&'a mut Map<String, Value>>,
+    redaction_rules: &'a RedactionRules,
 }
 
 #[optarg_impl]
@@ -28,11 +23,13 @@ impl<'a> SensitiveData<'a> {
     pub fn new(
         value: Map<String, Value>,
         #[optarg_default] extra_sensitive_keys: &'a [&'a str],
+        #[optarg_default] redaction_rules: Option<&'a RedactionRules>,
     ) -> Self {
         Self {
             hidden_value: Map::with_capacity(value.len()),
             shown_value: value,
             extra_sensitive_keys,
+            redaction_rules: redaction_rules.unwrap_or_else(|| redaction::rules()),
         }
     }
 
@@ -45,114 +42,326 @@ impl<'a> SensitiveData<'a> {
     }
 
     pub fn hide(&mut self) {
-        // A stack of references to the sensitive data to be processed by the below logic
-        let mut sensitive_data_stack = vec![SensitiveDataMut {
-            hidden_value: &mut self.hidden_value,
-            shown_value: &mut self.shown_value,
-        }];
-
-        while let Some(sensitive_data) = sensitive_data_stack.pop() {
-            // Move sensitive entries from shown_value to hidden_value
-            for &sensitive_key in constants::SENSITIVE_KEYS
-                .iter()
-                .chain(self.extra_sensitive_keys)
-            {
-                if let Some((sensitive_key, sensitive_value)) =
-                    sensitive_data.shown_value.remove_entry(sensitive_key)
+        let rules = self.redaction_rules;
+
+        // The extra sensitive keys are always removed
+        let mask_strategy = |path: &[String], value: &Value| {
+            rules.mask_strategy(path, value).or_else(|| {
+                path.last()
+                    .filter(|key| {
+                        self.extra_sensitive_keys
+                            .iter()
+                            .any(|sensitive_key| sensitive_key.eq_ignore_ascii_case(key))
+                    })
+                    .map(|_| MaskStrategy::Remove)
+            })
+        };
+
+        let mask = |path: &[String], value: &Value| {
+            mask_strategy(path, value).map(|strategy| rules.mask_value(strategy, value))
+        };
+
+        hide_entries(
+            &mut self.shown_value,
+            &mut self.hidden_value,
+            &mut vec![],
+            &mask,
+        );
+    }
+
+    pub fn show(&mut self) {
+        show_entries(&mut self.shown_value, mem::take(&mut self.hidden_value));
+    }
+}
+
+// Returns None for an entry which is not sensitive, or else its masked value if it is kept in place
+trait Mask: Fn(&[String], &Value) -> Option<Option<Value>> {}
+
+impl<F: Fn(&[String], &Value) -> Option<Option<Value>>> Mask for F {}
+
+// Moves sensitive entries from shown_value to hidden_value, at any depth
+fn hide_entries(
+    shown_value: &mut Map<String, Value>,
+    hidden_value: &mut Map<String, Value>,
+    path: &mut Vec<String>,
+    mask: &impl Mask,
+) {
+    let keys = shown_value.keys().cloned().collect::<Vec<_>>();
+
+    for k in keys {
+        path.push(k.to_string());
+
+        match mask(path, &shown_value[&k]) {
+            Some(Some(masked_value)) => {
+                if let Some(sv) = shown_value.get_mut(&k) {
+                    hidden_value.insert(k, mem::replace(sv, masked_value));
+                }
+            }
+            Some(None) => {
+                if let Some(v) = shown_value.remove(&k) {
+                    hidden_value.insert(k, v);
+                }
+            }
+            None => {
+                if let Some(hv) = shown_value
+                    .get_mut(&k)
+                    .and_then(|sv| hide_children(sv, path, mask))
                 {
-                    sensitive_data
-                        .hidden_value
-                        .insert(sensitive_key, sensitive_value);
+                    hidden_value.insert(k, hv);
                 }
             }
+        }
 
-            let mut sensitive_data_map = HashMap::with_capacity(sensitive_data.shown_value.len());
+        path.pop();
+    }
+}
 
-            for (k, v) in sensitive_data.shown_value {
-                let Value::Object(sv) = v else {
-                    continue;
-                };
+// Array items cannot be removed without shifting the items after them, so removed items are
+// replaced with null instead, and the hidden items are keyed by their index
+fn hide_items(
+    shown_value: &mut [Value],
+    hidden_value: &mut Map<String, Value>,
+    path: &mut Vec<String>,
+    mask: &impl Mask,
+) {
+    for (i, sv) in shown_value.iter_mut().enumerate() {
+        path.push(i.to_string());
 
-                sensitive_data
-                    .hidden_value
-                    .insert(k.to_string(), json!(Map::with_capacity(sv.len())));
+        match mask(path, sv) {
+            Some(masked_value) => {
+                hidden_value.insert(
+                    i.to_string(),
+                    mem::replace(sv, masked_value.unwrap_or(Value::Null)),
+                );
+            }
+            None => {
+                if let Some(hv) = hide_children(sv, path, mask) {
+                    hidden_value.insert(i.to_string(), hv);
+                }
+            }
+        }
 
-                let v = OptionalSensitiveDataMut {
-                    hidden_value: None,
-                    shown_value: Some(sv),
-                };
+        path.pop();
+    }
+}
 
-                sensitive_data_map.insert(k, v);
-            }
+// Returns the hidden children of an object or array, if any
+fn hide_children(
+    shown_value: &mut Value,
+    path: &mut Vec<String>,
+    mask: &impl Mask,
+) -> Option<Value> {
+    let mut hidden_value = Map::new();
+
+    match shown_value {
+        Value::Object(sv) => hide_entries(sv, &mut hidden_value, path, mask),
+        Value::Array(sv) => hide_items(sv, &mut hidden_value, path, mask),
+        _ => return None,
+    }
+
+    if hidden_value.is_empty() {
+        None
+    } else {
+        Some(Value::Object(hidden_value))
+    }
+}
 
-            for (k, v) in sensitive_data.hidden_value {
-                sensitive_data_map.entry(k).and_modify(|sensitive_data| {
-                    sensitive_data.hidden_value = Some(v.as_object_mut().unwrap())
-                });
+// Moves hidden entries back from hidden_value to shown_value. Masked values are never objects or
+// arrays, so an object or array found in shown_value is the parent of hidden entries rather than
+// the masked value of a hidden entry
+fn show_entries(shown_value: &mut Map<String, Value>, hidden_value: Map<String, Value>) {
+    for (k, hv) in hidden_value {
+        match shown_value.get_mut(&k) {
+            Some(sv) => show_value(sv, hv),
+            None => {
+                shown_value.insert(k, hv);
             }
+        }
+    }
+}
 
-            // Queue up the rest of entries to be processed in the next iteration
-            // Will be processed in LIFO order because using the stack
-            let sensitive_data_iter =
-                sensitive_data_map
-                    .into_values()
-                    .map(|sensitive_data| SensitiveDataMut {
-                        hidden_value: sensitive_data.hidden_value.unwrap(),
-                        shown_value: sensitive_data.shown_value.unwrap(),
-                    });
-
-            sensitive_data_stack.extend(sensitive_data_iter);
+fn show_items(shown_value: &mut [Value], hidden_value: Map<String, Value>) {
+    for (i, hv) in hidden_value {
+        if let Some(sv) = i.parse::<usize>().ok().and_then(|i| shown_value.get_mut(i)) {
+            show_value(sv, hv);
         }
     }
+}
 
-    pub fn show(&mut self) {
-        // A stack of references to the sensitive data to be processed by the below logic
-        let mut sensitive_data_stack = vec![SensitiveDataMut {
-            hidden_value: &mut self.hidden_value,
-            shown_value: &mut self.shown_value,
-        }];
-
-        while let Some(sensitive_data) = sensitive_data_stack.pop() {
-            // Move sensitive entries from hidden_value to shown_value
-            for (k, v) in sensitive_data.hidden_value.iter_mut() {
-                sensitive_data
-                    .shown_value
-                    .entry(k)
-                    .or_insert_with(|| v.take());
-            }
+fn show_value(shown_value: &mut Value, hidden_value: Value) {
+    match (shown_value, hidden_value) {
+        (Value::Object(sv), Value::Object(hv)) => show_entries(sv, hv),
+        (Value::Array(sv), Value::Object(hv)) => show_items(sv, hv),
+        (sv, hv) => *sv = hv,
+    }
+}
 
-            let mut sensitive_data_map = HashMap::with_capacity(sensitive_data.hidden_value.len());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_enums::ValueDetector;
+    use proptest::prelude::*;
+    use serde_json::json;
 
-            for (k, v) in sensitive_data.hidden_value {
-                let Value::Object(hv) = v else {
-                    continue;
-                };
+    const MASK_STRATEGIES: [MaskStrategy; 5] = [
+        MaskStrategy::Remove,
+        MaskStrategy::Replace,
+        MaskStrategy::LastFour,
+        MaskStrategy::PreserveLength,
+        MaskStrategy::Fingerprint,
+    ];
 
-                let v = OptionalSensitiveDataMut {
-                    hidden_value: Some(hv),
-                    shown_value: None,
-                };
+    fn rules(strategy: MaskStrategy) -> RedactionRules {
+        RedactionRules::default()
+            .mask(strategy)
+            .fingerprint_key(b"fingerprint key")
+            .key("card")
+            .path("/items/*")
+            .detect(ValueDetector::CardNumber)
+            .detect(ValueDetector::Email)
+    }
 
-                sensitive_data_map.insert(k, v);
-            }
+    fn hide(value: Value, rules: &RedactionRules) -> SensitiveData<'_> {
+        let Value::Object(value) = value else {
+            panic!("{value} is not an object");
+        };
 
-            for (k, v) in sensitive_data.shown_value {
-                sensitive_data_map.entry(k).and_modify(|sensitive_data| {
-                    sensitive_data.shown_value = Some(v.as_object_mut().unwrap());
-                });
-            }
+        let mut data = SensitiveData::new(value)
+            .extra_sensitive_keys(&["pin"][..])
+            .redaction_rules(Some(rules))
+            .call();
+
+        data.hide();
+        data
+    }
+
+    fn arb_key() -> impl Strategy<Value = String> {
+        prop_oneof![
+            prop::sample::select(vec!["password", "card", "pin", "items", "userToken", "0"])
+                .prop_map(str::to_string),
+            "[a-z]{1,6}",
+        ]
+    }
+
+    fn arb_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            (-1e9..1e9f64).prop_map(Value::from),
+            ".{0,12}".prop_map(Value::from),
+            prop::sample::select(vec!["4111 1111 1111 1111", "user@example.com"])
+                .prop_map(Value::from),
+        ];
+
+        leaf.prop_recursive(4, 64, 6, |value| {
+            prop_oneof![
+                prop::collection::vec(value.clone(), 0..6).prop_map(Value::from),
+                prop::collection::btree_map(arb_key(), value, 0..6)
+                    .prop_map(|value| Value::Object(value.into_iter().collect())),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn show_restores_what_hide_hid(
+            value in prop::collection::btree_map(arb_key(), arb_value(), 0..6),
+            strategy in prop::sample::select(MASK_STRATEGIES.to_vec()),
+        ) {
+            let value = Value::Object(value.into_iter().collect());
+            let rules = rules(strategy);
+            let mut data = hide(value.clone(), &rules);
 
-            // Queue up the rest of entries to be processed in the next iteration
-            // Will be processed in LIFO order because using the stack
-            let sensitive_data_iter =
-                sensitive_data_map
-                    .into_values()
-                    .map(|sensitive_data| SensitiveDataMut {
-                        hidden_value: sensitive_data.hidden_value.unwrap(),
-                        shown_value: sensitive_data.shown_value.unwrap(),
-                    });
-
-            sensitive_data_stack.extend(sensitive_data_iter);
+            data.show();
+
+            prop_assert_eq!(Value::Object(data.into_data()), value);
         }
     }
+
+    #[test]
+    fn arrays_nested_at_any_depth_are_hidden() {
+        let value = json!({
+            "a": [[{ "password": "x" }, 1], [[{ "userToken": "y", "b": [2, { "pin": "3" }] }]]],
+        });
+
+        let rules = rules(MaskStrategy::Remove);
+        let mut data = hide(value.clone(), &rules);
+
+        assert_eq!(
+            Value::Object(data.get().clone()),
+            json!({ "a": [[{}, 1], [[{ "b": [2, {}] }]]] })
+        );
+
+        data.show();
+        assert_eq!(Value::Object(data.into_data()), value);
+    }
+
+    #[test]
+    fn sensitive_item_already_null_is_restored() {
+        let value = json!({ "items": [null, "a", null] });
+        let rules = rules(MaskStrategy::Remove);
+        let mut data = hide(value.clone(), &rules);
+
+        assert_eq!(
+            Value::Object(data.get().clone()),
+            json!({ "items": [null, null, null] })
+        );
+
+        data.show();
+        assert_eq!(Value::Object(data.into_data()), value);
+    }
+
+    #[test]
+    fn items_are_masked_with_each_strategy() {
+        let value = json!({
+            "card": "4111111111111111",
+            "list": ["4111 1111 1111 1111", { "email": "user@example.com" }],
+        });
+
+        for (strategy, card, list) in [
+            (MaskStrategy::Remove, None, json!([null, {}])),
+            (
+                MaskStrategy::Replace,
+                Some(json!("***")),
+                json!(["***", { "email": "***" }]),
+            ),
+            (
+                MaskStrategy::LastFour,
+                Some(json!("***1111")),
+                json!(["***1111", { "email": "***.com" }]),
+            ),
+            (
+                MaskStrategy::PreserveLength,
+                Some(json!("*".repeat(16))),
+                json!(["*".repeat(19), { "email": "*".repeat(16) }]),
+            ),
+        ] {
+            let rules = rules(strategy);
+            let mut data = hide(value.clone(), &rules);
+
+            assert_eq!(data.get().get("card"), card.as_ref());
+            assert_eq!(data.get()["list"], list);
+
+            data.show();
+            assert_eq!(Value::Object(data.into_data()), value);
+        }
+
+        let rules = rules(MaskStrategy::Fingerprint);
+        let mut data = hide(value.clone(), &rules);
+
+        // The same value always gets the same fingerprint
+        assert_eq!(
+            data.get()["card"],
+            rules
+                .mask_value(MaskStrategy::Fingerprint, &value["card"])
+                .unwrap()
+        );
+        assert!(data.get()["list"][0]
+            .as_str()
+            .is_some_and(|item| item.starts_with("hmac:")));
+
+        data.show();
+        assert_eq!(Value::Object(data.into_data()), value);
+    }
 }
@@ -6,6 +6,7 @@ use std::{
     fmt::{self, Formatter},
     ops::{Deref, DerefMut},
 };
+use validator::ValidateLength;
 
 #[derive(Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TrimmedString(String);
@@ -36,6 +37,13 @@ impl DerefMut for TrimmedString {
     }
 }
 
+// Also lets Secret<TrimmedString> be validated, which has no Deref to fall back on
+impl ValidateLength<u64> for TrimmedString {
+    fn length(&self) -> Option<u64> {
+        self.0.length()
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
 struct TrimmedStringVisitor;
 
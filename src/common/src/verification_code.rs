@@ -0,0 +1,316 @@
+use crate::{error_code::CommonErrorCode, CommonError};
+use lambda_runtime::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+pub const DIGITS: &[u8] = b"0123456789";
+
+// Without 0, O, 1 and I, which are easily mistaken for each other when typed in
+pub const ALPHANUMERIC: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+// Meant to be declared once per flow as a const, e.g.
+// const SIGNUP_CODE_POLICY: VerificationCodePolicy = VerificationCodePolicy {
+//     ttl_minutes: 30,
+//     ..VerificationCodePolicy::DEFAULT
+// };
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VerificationCodePolicy {
+    pub char_count: usize,
+    pub alphabet: &'static [u8],
+    pub ttl_minutes: u32,
+
+    // Wrong codes allowed before the code gets locked for lockout_minutes
+    pub max_attempt_count: u32,
+
+    pub lockout_minutes: u32,
+}
+
+impl VerificationCodePolicy {
+    pub const DEFAULT: Self = Self {
+        char_count: 6,
+        alphabet: DIGITS,
+        ttl_minutes: 10,
+        max_attempt_count: 5,
+        lockout_minutes: 15,
+    };
+
+    // Returns the code to send to the user, and the record to store in its place. The code is
+    // hashed on the blocking pool, giving up when the Lambda function is about to time out
+    pub async fn issue(
+        &self,
+        context: &Context,
+    ) -> Result<(String, VerificationCodeRecord), VerificationCodeError> {
+        let code = crate::gen_secret_chars(self.char_count, self.alphabet)
+            .map_err(VerificationCodeError::Alphabet)?;
+
+        let expires_at = crate::extend_current_timestamp()
+            .minutes(i64::from(self.ttl_minutes))
+            .call()
+            .map_err(VerificationCodeError::Clock)?;
+
+        let record = VerificationCodeRecord {
+            code_hash: crate::hash_secret_async(&code, context)
+                .await
+                .map_err(VerificationCodeError::Hash)?,
+            expires_at,
+            ..Default::default()
+        };
+
+        Ok((code, record))
+    }
+
+    // Updates the record, which has to be stored again whether or not the code matches
+    pub async fn verify(
+        &self,
+        record: &mut VerificationCodeRecord,
+        code: &str,
+        context: &Context,
+    ) -> Result<(), VerificationCodeError> {
+        let now = crate::get_current_timestamp()
+            .call()
+            .map_err(VerificationCodeError::Clock)?;
+
+        if record.is_used {
+            return Err(VerificationCodeError::Used);
+        }
+
+        if let Some(locked_until) = record
+            .locked_until
+            .filter(|&locked_until| locked_until > now)
+        {
+            return Err(VerificationCodeError::Locked { locked_until });
+        }
+
+        if record.expires_at <= now {
+            return Err(VerificationCodeError::Expired);
+        }
+
+        // The hash comparison takes the same time however many chars match
+        let is_matched = crate::verify_secret_async(code.trim(), &record.code_hash, context)
+            .await
+            .map_err(VerificationCodeError::Hash)?;
+
+        if is_matched {
+            record.is_used = true;
+            return Ok(());
+        }
+
+        record.attempt_count += 1;
+
+        if record.attempt_count < self.max_attempt_count {
+            return Err(VerificationCodeError::Mismatch {
+                remaining_attempt_count: self.max_attempt_count - record.attempt_count,
+            });
+        }
+
+        let locked_until = crate::extend_current_timestamp()
+            .src_timestamp(now)
+            .minutes(i64::from(self.lockout_minutes))
+            .call()
+            .map_err(VerificationCodeError::Clock)?;
+
+        record.attempt_count = 0;
+        record.locked_until = Some(locked_until);
+        Err(VerificationCodeError::Locked { locked_until })
+    }
+}
+
+impl Default for VerificationCodePolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// What gets stored for each verification code, which never includes the code itself
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct VerificationCodeRecord {
+    pub code_hash: String,
+    pub expires_at: u64,
+    pub attempt_count: u32,
+    pub locked_until: Option<u64>,
+    pub is_used: bool,
+}
+
+#[derive(Debug)]
+pub enum VerificationCodeError {
+    Alphabet(anyhow::Error),
+    Hash(anyhow::Error),
+    Clock(anyhow::Error),
+    Used,
+    Expired,
+    Locked { locked_until: u64 },
+    Mismatch { remaining_attempt_count: u32 },
+}
+
+impl Display for VerificationCodeError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Alphabet(err) => write!(fmt, "Invalid verification code alphabet: {err}"),
+            Self::Hash(err) => write!(fmt, "Failed to hash the verification code: {err}"),
+            Self::Clock(err) => write!(fmt, "Failed to get the current time: {err}"),
+            Self::Used => write!(fmt, "Verification code has already been used"),
+            Self::Expired => write!(fmt, "Verification code has expired"),
+            Self::Locked { locked_until } => {
+                write!(fmt, "Verification code is locked until {locked_until}")
+            }
+            Self::Mismatch {
+                remaining_attempt_count,
+            } => write!(
+                fmt,
+                "Verification code is wrong, {remaining_attempt_count} attempts remaining"
+            ),
+        }
+    }
+}
+
+impl Error for VerificationCodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Alphabet(err) | Self::Hash(err) | Self::Clock(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<VerificationCodeError> for CommonError {
+    fn from(err: VerificationCodeError) -> Self {
+        let code = match err {
+            VerificationCodeError::Alphabet(_)
+            | VerificationCodeError::Hash(_)
+            | VerificationCodeError::Clock(_) => {
+                return CommonErrorCode::InternalServerError.into();
+            }
+            VerificationCodeError::Locked { .. } => CommonErrorCode::Forbidden,
+            _ => CommonErrorCode::Unauthorized,
+        };
+
+        CommonError {
+            code: code.into(),
+            message: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use tokio::runtime::Builder;
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(fut)
+    }
+
+    fn context() -> Context {
+        let mut context = Context::default();
+        context.deadline = crate::extend_current_timestamp()
+            .unit(crate::Unit::Milliseconds)
+            .minutes(1i64)
+            .call()
+            .unwrap();
+
+        context
+    }
+
+    #[test]
+    fn issued_code_is_verified_once() {
+        let context = context();
+
+        block_on(async {
+            let (code, mut record) = VerificationCodePolicy::DEFAULT
+                .issue(&context)
+                .await
+                .unwrap();
+
+            assert_eq!(code.len(), 6);
+            assert!(code.bytes().all(|byte| DIGITS.contains(&byte)));
+
+            VerificationCodePolicy::DEFAULT
+                .verify(&mut record, &format!(" {code} "), &context)
+                .await
+                .unwrap();
+
+            assert!(matches!(
+                VerificationCodePolicy::DEFAULT
+                    .verify(&mut record, &code, &context)
+                    .await,
+                Err(VerificationCodeError::Used)
+            ));
+        });
+    }
+
+    #[test]
+    fn wrong_codes_lock_the_code() {
+        let context = context();
+        let policy = VerificationCodePolicy {
+            max_attempt_count: 2,
+            ..VerificationCodePolicy::DEFAULT
+        };
+
+        block_on(async {
+            let (code, mut record) = policy.issue(&context).await.unwrap();
+            let wrong_code = if code == "000000" { "111111" } else { "000000" };
+
+            assert!(matches!(
+                policy.verify(&mut record, wrong_code, &context).await,
+                Err(VerificationCodeError::Mismatch {
+                    remaining_attempt_count: 1
+                })
+            ));
+            assert!(matches!(
+                policy.verify(&mut record, wrong_code, &context).await,
+                Err(VerificationCodeError::Locked { .. })
+            ));
+            assert!(matches!(
+                policy.verify(&mut record, &code, &context).await,
+                Err(VerificationCodeError::Locked { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn longest_lockout_is_in_the_future() {
+        let context = context();
+        let policy = VerificationCodePolicy {
+            max_attempt_count: 1,
+            lockout_minutes: u32::MAX,
+            ..VerificationCodePolicy::DEFAULT
+        };
+
+        block_on(async {
+            let (code, mut record) = policy.issue(&context).await.unwrap();
+            let wrong_code = if code == "000000" { "111111" } else { "000000" };
+            let now = crate::get_current_timestamp().call().unwrap();
+
+            assert!(matches!(
+                policy.verify(&mut record, wrong_code, &context).await,
+                Err(VerificationCodeError::Locked { locked_until })
+                    if locked_until >= now + u64::from(u32::MAX) * 60
+            ));
+        });
+    }
+
+    #[test]
+    fn invalid_alphabet_is_an_error() {
+        let context = context();
+
+        for alphabet in [&[][..], &[b'A'; 257][..]] {
+            let policy = VerificationCodePolicy {
+                alphabet,
+                ..VerificationCodePolicy::DEFAULT
+            };
+
+            assert!(matches!(
+                block_on(policy.issue(&context)),
+                Err(VerificationCodeError::Alphabet(_))
+            ));
+        }
+    }
+}
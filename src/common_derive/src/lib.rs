@@ -0,0 +1,49 @@
+#![deny(elided_lifetimes_in_paths)]
+
+mod request;
+mod sensitive;
+mod serde_attr;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput, Error};
+
+// Masks the fields marked with #[sensitive] in Debug and Display, and lists their serialized names
+// in common::Sensitive::SENSITIVE_KEYS so that they are also masked when serialized with Redacted,
+// e.g.
+// #[derive(Deserialize, Serialize, Sensitive)]
+// #[serde(rename_all = "camelCase")]
+// struct SignUpRequest {
+//     username: TrimmedString,
+//
+//     #[sensitive]
+//     password: TrimmedString,
+// }
+#[proc_macro_derive(Sensitive, attributes(sensitive))]
+pub fn derive_sensitive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    sensitive::expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+// Implements common::common_serde::Request, generating FIELD_SOURCES from the fields marked with
+// #[request(source(...))] under their deserialized names, e.g.
+// #[derive(Deserialize, Validate, Request)]
+// #[serde(rename_all = "camelCase")]
+// #[request(merge_policy = "reject_on_conflict")]
+// struct UpdateUserRequest {
+//     #[request(source(path))]
+//     user_id: String,
+//
+//     #[request(source(body, query))]
+//     display_name: String,
+// }
+#[proc_macro_derive(Request, attributes(request))]
+pub fn derive_request(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    request::expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
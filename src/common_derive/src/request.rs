@@ -0,0 +1,128 @@
+use crate::serde_attr;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{ext::IdentExt, Attribute, Data, DeriveInput, Error, Fields, Ident, LitStr};
+
+pub(crate) fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            input,
+            "Request can only be derived for structs",
+        ));
+    };
+
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            input,
+            "Request can only be derived for structs with named fields",
+        ));
+    };
+
+    let name = &input.ident;
+    let rename_all = serde_attr::get_rename(&input.attrs, "rename_all", "deserialize")?;
+    let merge_policy = get_merge_policy(&input.attrs)?.map(|merge_policy| {
+        quote! {
+            const MERGE_POLICY: ::common::common_enums::MergePolicy =
+                ::common::common_enums::MergePolicy::#merge_policy;
+        }
+    });
+
+    let mut field_sources = vec![];
+
+    for field in &fields.named {
+        let Some(sources) = get_sources(&field.attrs)? else {
+            continue;
+        };
+
+        let key = match serde_attr::get_rename(&field.attrs, "rename", "deserialize")? {
+            Some(key) => key,
+            None => serde_attr::rename(
+                &field.ident.as_ref().unwrap().unraw().to_string(),
+                rename_all.as_deref(),
+            )?,
+        };
+
+        field_sources.push(quote! {
+            (#key, &[#(::common::common_enums::RequestSource::#sources),*])
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::common::common_serde::Request for #name #ty_generics #where_clause {
+            #merge_policy
+
+            const FIELD_SOURCES: &'static [(
+                &'static str,
+                &'static [::common::common_enums::RequestSource],
+            )] = &[#(#field_sources),*];
+        }
+    })
+}
+
+// Reads #[request(merge_policy = "reject_on_conflict")]
+fn get_merge_policy(attrs: &[Attribute]) -> syn::Result<Option<Ident>> {
+    let mut merge_policy = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("request")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("merge_policy") {
+                return Err(meta.error("Unknown request attribute, expected merge_policy"));
+            }
+
+            let value = meta.value()?.parse::<LitStr>()?;
+            let variant =
+                match value.value().as_str() {
+                    "body_wins" => "BodyWins",
+                    "path_wins" => "PathWins",
+                    "reject_on_conflict" => "RejectOnConflict",
+                    _ => return Err(Error::new_spanned(
+                        value,
+                        "Unknown merge policy, expected body_wins, path_wins or reject_on_conflict",
+                    )),
+                };
+
+            merge_policy = Some(Ident::new(variant, value.span()));
+            Ok(())
+        })?;
+    }
+
+    Ok(merge_policy)
+}
+
+// Reads #[request(source(path, query))]
+fn get_sources(attrs: &[Attribute]) -> syn::Result<Option<Vec<Ident>>> {
+    let mut sources = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("request")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("source") {
+                return Err(meta.error("Unknown request attribute, expected source"));
+            }
+
+            let sources = sources.get_or_insert_with(Vec::new);
+
+            if meta.input.is_empty() {
+                return Err(meta.error("Expected source(body, path or query)"));
+            }
+
+            meta.parse_nested_meta(|meta| {
+                let variant = if meta.path.is_ident("body") {
+                    "Body"
+                } else if meta.path.is_ident("path") {
+                    "Path"
+                } else if meta.path.is_ident("query") {
+                    "Query"
+                } else {
+                    return Err(meta.error("Unknown request source, expected body, path or query"));
+                };
+
+                sources.push(Ident::new(variant, meta.path.get_ident().unwrap().span()));
+                Ok(())
+            })
+        })?;
+    }
+
+    Ok(sources)
+}
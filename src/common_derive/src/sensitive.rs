@@ -0,0 +1,102 @@
+use crate::serde_attr;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{ext::IdentExt, parse_quote, Data, DeriveInput, Error, Fields, GenericParam, Index};
+
+pub(crate) fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            input,
+            "Sensitive can only be derived for structs",
+        ));
+    };
+
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let rename_all = serde_attr::get_rename(&input.attrs, "rename_all", "serialize")?;
+    let mut debug_fields = vec![];
+    let mut sensitive_keys = vec![];
+
+    for (i, field) in data.fields.iter().enumerate() {
+        let is_sensitive = field
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("sensitive"));
+
+        let value = match &field.ident {
+            _ if is_sensitive => quote!(&::core::format_args!("***")),
+            Some(ident) => quote!(&self.#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(&self.#index)
+            }
+        };
+
+        let Some(ident) = &field.ident else {
+            debug_fields.push(quote!(.field(#value)));
+
+            if is_sensitive {
+                sensitive_keys.push(i.to_string());
+            }
+
+            continue;
+        };
+
+        let ident_str = ident.unraw().to_string();
+        debug_fields.push(quote!(.field(#ident_str, #value)));
+
+        if is_sensitive {
+            let key = match serde_attr::get_rename(&field.attrs, "rename", "serialize")? {
+                Some(key) => key,
+                None => serde_attr::rename(&ident_str, rename_all.as_deref())?,
+            };
+
+            sensitive_keys.push(key);
+        }
+    }
+
+    let debug = match data.fields {
+        Fields::Named(_) => quote!(debug_struct),
+        Fields::Unnamed(_) | Fields::Unit => quote!(debug_tuple),
+    };
+
+    // Every type param has to be Debug, as #[derive(Debug)] requires
+    let mut debug_generics = input.generics.clone();
+
+    for param in &input.generics.params {
+        if let GenericParam::Type(param) = param {
+            let ident = &param.ident;
+
+            debug_generics
+                .make_where_clause()
+                .predicates
+                .push(parse_quote!(#ident: ::core::fmt::Debug));
+        }
+    }
+
+    let (impl_generics, ty_generics, where_clause) = debug_generics.split_for_impl();
+
+    let (sensitive_impl_generics, sensitive_ty_generics, sensitive_where_clause) =
+        input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::core::fmt::Debug for #name #ty_generics #where_clause {
+            fn fmt(&self, fmt: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                fmt.#debug(#name_str) #(#debug_fields)* .finish()
+            }
+        }
+
+        // The same as Debug, so that neither of them prints the marked fields
+        impl #impl_generics ::core::fmt::Display for #name #ty_generics #where_clause {
+            fn fmt(&self, fmt: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                ::core::fmt::Debug::fmt(self, fmt)
+            }
+        }
+
+        impl #sensitive_impl_generics ::common::Sensitive
+            for #name #sensitive_ty_generics #sensitive_where_clause
+        {
+            const SENSITIVE_KEYS: &'static [&'static str] = &[#(#sensitive_keys),*];
+        }
+    })
+}
@@ -0,0 +1,87 @@
+use syn::{meta::ParseNestedMeta, token, Attribute, Error, Expr, LitStr, Token};
+
+// Reads #[serde(rename = "...")] or #[serde(rename_all = "...")], including the
+// #[serde(rename(serialize = "..."))] form where direction is either serialize or deserialize
+pub(crate) fn get_rename(
+    attrs: &[Attribute],
+    name: &str,
+    direction: &str,
+) -> syn::Result<Option<String>> {
+    let mut rename = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident(name) {
+                return skip_meta(&meta);
+            }
+
+            if meta.input.peek(Token![=]) {
+                rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                return Ok(());
+            }
+
+            meta.parse_nested_meta(|meta| {
+                if meta.path.is_ident(direction) {
+                    rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                    Ok(())
+                } else {
+                    skip_meta(&meta)
+                }
+            })
+        })?;
+    }
+
+    Ok(rename)
+}
+
+// Every other serde attribute has to be parsed through to get to the next one
+pub(crate) fn skip_meta(meta: &ParseNestedMeta<'_>) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(token::Paren) {
+        meta.parse_nested_meta(|meta| skip_meta(&meta))?;
+    }
+
+    Ok(())
+}
+
+// The same rules as serde's rename_all, for snake_case field names
+pub(crate) fn rename(field_name: &str, rename_all: Option<&str>) -> syn::Result<String> {
+    let pascal_case = || {
+        field_name
+            .split('_')
+            .map(|word| {
+                let mut chars = word.chars();
+
+                chars
+                    .next()
+                    .map(|first_char| first_char.to_ascii_uppercase().to_string() + chars.as_str())
+                    .unwrap_or_default()
+            })
+            .collect::<String>()
+    };
+
+    Ok(match rename_all {
+        None | Some("snake_case") => field_name.to_string(),
+        Some("lowercase") => field_name.to_ascii_lowercase(),
+        Some("UPPERCASE") | Some("SCREAMING_SNAKE_CASE") => field_name.to_ascii_uppercase(),
+        Some("PascalCase") => pascal_case(),
+        Some("camelCase") => {
+            let pascal_case = pascal_case();
+            let mut chars = pascal_case.chars();
+
+            chars
+                .next()
+                .map(|first_char| first_char.to_ascii_lowercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        }
+        Some("kebab-case") => field_name.replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => field_name.to_ascii_uppercase().replace('_', "-"),
+        Some(rename_all) => {
+            return Err(Error::new(
+                proc_macro2::Span::call_site(),
+                format!("Unknown serde rename_all rule: {rename_all}"),
+            ))
+        }
+    })
+}